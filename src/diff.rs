@@ -0,0 +1,332 @@
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Kind, Ops, Value, HsonPath, unescape };
+
+
+/// Semantic change between two documents.
+/// Changes are ordered, each path locates a node once the previous changes are applied
+#[derive(PartialEq, Clone, Debug)]
+pub enum Change {
+    /// Node added at the provided position of its parent childs
+    Added { path: HsonPath, position: usize, value: Value },
+    Removed { path: HsonPath, value: Value },
+    /// Node moved to the provided position among its siblings
+    Moved { from: HsonPath, to: HsonPath, position: usize },
+    ValueChanged { path: HsonPath, before: Value, after: Value },
+    KeyRenamed { path: HsonPath, before: String, after: String }
+}
+
+impl fmt::Display for Change {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added { path, value, .. } => write!(f, "+ {}: {}", path, value),
+            Change::Removed { path, value } => write!(f, "- {}: {}", path, value),
+            Change::Moved { from, to, .. } => write!(f, "> {} -> {}", from, to),
+            Change::ValueChanged { path, before, after } => write!(f, "~ {}: {} -> {}", path, before, after),
+            Change::KeyRenamed { path, before, after } => write!(f, "# {}: {} -> {}", path, before, after)
+        }
+    }
+}
+
+
+/// List the changes turning the first document into the second one.
+/// Object entries are paired by key and occurrence, unpaired entries holding the same
+/// value are renamed and paired entries out of order are moved.
+/// Array elements are aligned on their longest common subsequence.
+/// A document without data is taken as an empty object
+pub fn diff (a: &Hson, b: &Hson) -> Result<Vec<Change>, Error> {
+    let mut empty = Hson::new();
+    empty.parse("{}")?;
+
+    let work = if a.indexes.is_empty() { empty.clone() } else { a.fork() };
+    let target = if b.indexes.is_empty() { &empty } else { b };
+
+    let mut differ = Differ {
+        work,
+        target,
+        changes: Vec::new()
+    };
+
+    // Every change is applied to a copy of the first document to follow the paths
+    let (root, target_root) = (differ.work.indexes[0], target.indexes[0]);
+    differ.node(root, target_root)?;
+
+    Ok(differ.changes)
+}
+
+
+/// Array alignment step
+enum Step {
+    Keep,
+    Pair(u64, u64),
+    Remove(u64),
+    Add(u64)
+}
+
+struct Differ<'a> {
+    work: Hson,
+    target: &'a Hson,
+    changes: Vec<Change>
+}
+
+impl<'a> Differ<'a> {
+    fn path (&self, node_id: u64) -> HsonPath {
+        self.work.path_of(node_id).unwrap_or_default()
+    }
+
+    fn value (hson: &Hson, node_id: u64) -> Value {
+        hson.get_value(node_id).unwrap_or(Value::Null)
+    }
+
+    fn key (hson: &Hson, node_id: u64) -> String {
+        unescape(&hson.get_node_key(&hson.nodes[&node_id]))
+    }
+
+    /// Position of a node in the working document parent childs
+    fn position (&self, parent_id: u64, node_id: u64) -> Result<usize, Error> {
+        match self.work.nodes[&parent_id].childs.iter().position(|id| *id == node_id) {
+            Some(position) => Ok(position),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("{} is not a child of {}", node_id, parent_id));
+                Err(e)
+            }
+        }
+    }
+
+    /// Turn a node of the working document into a node of the target
+    fn node (&mut self, node_id: u64, target_id: u64) -> Result<(), Error> {
+        let kind = self.work.nodes[&node_id].kind.clone();
+        let target_kind = self.target.nodes[&target_id].kind.clone();
+
+        match (kind, target_kind) {
+            (Kind::Node, Kind::Node) => self.entries(node_id, target_id),
+            (Kind::Array, Kind::Array) => self.elements(node_id, target_id),
+            _ => {
+                if !self.work.subtree_eq(node_id, self.target, target_id) {
+                    let before = Differ::value(&self.work, node_id);
+                    let after = Differ::value(self.target, target_id);
+
+                    self.work.set_value(node_id, after.clone())?;
+                    self.changes.push(Change::ValueChanged { path: self.path(node_id), before, after });
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn entries (&mut self, node_id: u64, target_id: u64) -> Result<(), Error> {
+        let childs = self.work.nodes[&node_id].childs.clone();
+        let targets = self.target.nodes[&target_id].childs.clone();
+
+        // Pair entries by key and occurrence
+        let mut by_key: HashMap<String, Vec<u64>> = HashMap::new();
+        for uid in &childs {
+            by_key.entry(Differ::key(&self.work, *uid)).or_default().push(*uid);
+        }
+
+        let mut pairs: HashMap<u64, u64> = HashMap::new();
+        for uid in &targets {
+            if let Some(ids) = by_key.get_mut(&Differ::key(self.target, *uid)) {
+                if !ids.is_empty() {
+                    pairs.insert(*uid, ids.remove(0));
+                }
+            }
+        }
+
+        let paired: HashSet<u64> = pairs.values().cloned().collect();
+        let mut removed: Vec<u64> = childs.iter().filter(|id| !paired.contains(id)).cloned().collect();
+
+        // Unpaired entries holding the same value are renamed
+        let mut renamed = Vec::new();
+        for uid in &targets {
+            if pairs.contains_key(uid) {
+                continue;
+            }
+
+            if let Some(pos) = removed.iter().position(|id| self.work.subtree_eq(*id, self.target, *uid)) {
+                let id = removed.remove(pos);
+                pairs.insert(*uid, id);
+                renamed.push((id, *uid));
+            }
+        }
+
+        for uid in removed.iter().rev() {
+            let path = self.path(*uid);
+            let value = Differ::value(&self.work, *uid);
+
+            self.work.remove(*uid)?;
+            self.changes.push(Change::Removed { path, value });
+        }
+
+        for (id, uid) in renamed {
+            let path = self.path(id);
+            let before = Differ::key(&self.work, id);
+            let after = Differ::key(self.target, uid);
+
+            self.work.rename_key(id, &after)?;
+            self.changes.push(Change::KeyRenamed { path, before, after });
+        }
+
+        // Entries in the longest increasing run of target positions stay in place
+        let order: HashMap<u64, usize> = targets.iter().enumerate().filter_map(|(i, uid)| pairs.get(uid).map(|id| (*id, i))).collect();
+        let positions: Vec<(u64, usize)> = self.work.nodes[&node_id].childs.iter().filter_map(|id| order.get(id).map(|i| (*id, *i))).collect();
+        let stable = longest_increasing(&positions);
+
+        // Every other entry is placed right after its predecessor in the target
+        for (i, uid) in targets.iter().enumerate() {
+            let position = if i == 0 { 0 } else { self.position(node_id, pairs[&targets[i - 1]])? + 1 };
+
+            match pairs.get(uid).cloned() {
+                Some(id) => {
+                    if stable.contains(&id) {
+                        continue;
+                    }
+
+                    let current = self.position(node_id, id)?;
+                    let position = if current < position { position - 1 } else { position };
+
+                    if current != position {
+                        let from = self.path(id);
+
+                        self.work.move_node(id, node_id, position)?;
+                        self.changes.push(Change::Moved { from, to: self.path(id), position });
+                    }
+                },
+                None => {
+                    let extracted = match self.target.extract(*uid) {
+                        Some(extracted) => extracted,
+                        None => {
+                            let e = Error::new(ErrorKind::InvalidData, format!("Cannot extract {}", uid));
+                            return Err(e);
+                        }
+                    };
+                    let result = self.work.graft(node_id, position, extracted)?;
+                    let id = result.roots[0];
+
+                    pairs.insert(*uid, id);
+                    self.changes.push(Change::Added { path: self.path(id), position, value: Differ::value(self.target, *uid) });
+                }
+            }
+        }
+
+        for uid in &targets {
+            let id = pairs[uid];
+            self.node(id, *uid)?;
+        }
+
+        Ok(())
+    }
+
+    fn elements (&mut self, node_id: u64, target_id: u64) -> Result<(), Error> {
+        let childs = self.work.nodes[&node_id].childs.clone();
+        let targets = self.target.nodes[&target_id].childs.clone();
+        let n = childs.len();
+        let m = targets.len();
+
+        // Longest common subsequence of equal elements
+        let mut lengths = vec![vec![0; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = if self.work.subtree_eq(childs[i], self.target, targets[j]) {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+
+        // Elements between two common ones are paired by position
+        let mut steps = Vec::new();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            let keep = i < n && j < m && self.work.subtree_eq(childs[i], self.target, targets[j]) && lengths[i][j] == lengths[i + 1][j + 1] + 1;
+
+            if keep {
+                flush_gap(&mut steps, &mut removed, &mut added);
+                steps.push(Step::Keep);
+                i += 1;
+                j += 1;
+            } else if j < m && (i == n || lengths[i][j + 1] >= lengths[i + 1][j]) {
+                added.push(targets[j]);
+                j += 1;
+            } else {
+                removed.push(childs[i]);
+                i += 1;
+            }
+        }
+        flush_gap(&mut steps, &mut removed, &mut added);
+
+        let mut cursor = 0;
+        for step in steps {
+            match step {
+                Step::Keep => cursor += 1,
+                Step::Pair(id, uid) => {
+                    self.node(id, uid)?;
+                    cursor += 1;
+                },
+                Step::Remove(id) => {
+                    let path = self.path(id);
+                    let value = Differ::value(&self.work, id);
+
+                    self.work.remove(id)?;
+                    self.changes.push(Change::Removed { path, value });
+                },
+                Step::Add(uid) => {
+                    let value = Differ::value(self.target, uid);
+                    let id = self.work.array_insert(node_id, cursor, value.clone())?;
+
+                    self.changes.push(Change::Added { path: self.path(id), position: cursor, value });
+                    cursor += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn flush_gap (steps: &mut Vec<Step>, removed: &mut Vec<u64>, added: &mut Vec<u64>) {
+    let l = removed.len().min(added.len());
+
+    for (id, uid) in removed.iter().zip(added.iter()) {
+        steps.push(Step::Pair(*id, *uid));
+    }
+
+    for id in removed.drain(..).skip(l) {
+        steps.push(Step::Remove(id));
+    }
+
+    for uid in added.drain(..).skip(l) {
+        steps.push(Step::Add(uid));
+    }
+}
+
+/// Ids of the longest run of strictly increasing positions
+fn longest_increasing (positions: &[(u64, usize)]) -> HashSet<u64> {
+    let l = positions.len();
+    let mut lengths = vec![1; l];
+    let mut previous = vec![None; l];
+
+    for i in 0..l {
+        for j in 0..i {
+            if positions[j].1 < positions[i].1 && lengths[j] + 1 > lengths[i] {
+                lengths[i] = lengths[j] + 1;
+                previous[i] = Some(j);
+            }
+        }
+    }
+
+    let mut stable = HashSet::new();
+    let mut current = (0..l).max_by_key(|i| lengths[*i]);
+    while let Some(i) = current {
+        stable.insert(positions[i].0);
+        current = previous[i];
+    }
+
+    stable
+}
@@ -0,0 +1,245 @@
+use std::sync::Mutex;
+
+use crate::{ Hson, Value, MutationRecord, unescape };
+
+
+/// Events types
+#[derive(PartialEq, Clone, Debug)]
+pub enum Event {
+    Parse,
+    Insert,
+    Remove,
+    Update,
+    Move,
+    Replace
+}
+
+/// Event passed to the listeners
+#[derive(PartialEq, Clone, Debug)]
+pub struct EventInfo {
+    pub event: Event,
+    /// Parsed root, inserted top level node, or removed, updated, moved or replaced node
+    pub node: u64,
+    /// Parent of the node once the change is made, 0 for the root
+    pub parent: u64,
+    /// Position of the node in the parent childs once the change is made
+    pub position: usize,
+    /// Parent and position of a moved node before the move
+    pub from: Option<(u64, usize)>,
+    /// Ids of the nodes created by the change, in document order
+    pub inserted: Vec<u64>,
+    /// Ids of the nodes dropped by the change, in document order
+    pub removed: Vec<u64>,
+    /// Node value before the change
+    pub before: Option<Value>,
+    /// Node value after the change. For a replacement, the entries which replaced the node
+    pub after: Option<Value>,
+    /// Former and new unescaped keys of a renamed node
+    pub renamed: Option<(String, String)>
+}
+
+impl EventInfo {
+    pub(crate) fn new (event: Event, node: u64) -> EventInfo {
+        EventInfo {
+            event,
+            node,
+            parent: 0,
+            position: 0,
+            from: None,
+            inserted: Vec::new(),
+            removed: Vec::new(),
+            before: None,
+            after: None,
+            renamed: None
+        }
+    }
+}
+
+/// Listener handle returned by `subscribe`
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct SubscriptionId(u64);
+
+/// Event along with the records of the observers it concerns
+pub(crate) type Emitted = (EventInfo, Vec<(SubscriptionId, MutationRecord)>);
+
+/// Events listener, `Send` like the document
+pub type Listener = Box<dyn FnMut(&EventInfo) + Send>;
+
+/// Subscribed listeners
+#[derive(Default)]
+pub(crate) struct Listeners {
+    count: u64,
+    entries: Vec<(SubscriptionId, Mutex<Listener>)>
+}
+
+impl Listeners {
+    /// Call the listeners in subscription order
+    pub(crate) fn notify (&self, info: &EventInfo) {
+        for (_, listener) in &self.entries {
+            // A listener which panicked is not called anymore
+            if let Ok(mut listener) = listener.lock() {
+                listener(info);
+            }
+        }
+    }
+}
+
+
+impl Hson {
+    /// Add an events listener, listeners are called in subscription order
+    pub fn subscribe (&mut self, listener: Listener) -> SubscriptionId {
+        let id = self.next_subscription();
+        self.listeners.entries.push((id, Mutex::new(listener)));
+
+        id
+    }
+
+    /// Remove an events listener or a hook, returns false if it was not subscribed
+    pub fn unsubscribe (&mut self, id: SubscriptionId) -> bool {
+        let l = self.listeners.entries.len();
+        self.listeners.entries.retain(|(uid, _)| *uid != id);
+
+        self.listeners.entries.len() != l || self.hooks.remove(id) || self.observers.remove(id)
+    }
+
+    pub(crate) fn next_subscription (&mut self) -> SubscriptionId {
+        self.listeners.count += 1;
+
+        SubscriptionId(self.listeners.count)
+    }
+
+    /// Events are only described when someone listens
+    pub(crate) fn listening (&self) -> bool {
+        !self.listeners.entries.is_empty() || !self.observers.is_empty()
+    }
+
+    /// Call the listeners and the observers or hold the event until the current transaction ends
+    pub(crate) fn emit (&mut self, info: EventInfo) {
+        self.emit_all(vec![info]);
+    }
+
+    /// Emit the events of a single operation, observers get them in one batch
+    pub(crate) fn emit_all (&mut self, events: Vec<EventInfo>) {
+        let events: Vec<Emitted> = events.into_iter()
+            .map(|info| {
+                let records = self.observers.records(self, &info);
+                (info, records)
+            })
+            .collect();
+
+        match self.pending {
+            Some(ref mut pending) => pending.extend(events),
+            None => self.deliver(events)
+        }
+    }
+
+    /// Call the listeners for every event, then each observer once with its records
+    pub(crate) fn deliver (&self, events: Vec<Emitted>) {
+        let mut records = Vec::new();
+
+        for (info, mut matched) in events {
+            self.listeners.notify(&info);
+            records.append(&mut matched);
+        }
+
+        self.observers.deliver(records);
+    }
+
+    /// Describe a node before it is removed, updated, moved or replaced
+    pub(crate) fn capture (&self, event: Event, node_id: u64) -> Option<EventInfo> {
+        if !self.listening() {
+            return None;
+        }
+
+        let (parent, position) = self.sibling_position(node_id).ok()?;
+        let (start, stop) = self.subtree_range(node_id).ok()?;
+        let key = unescape(&self.get_node_key(&self.nodes[&node_id]));
+        let mut info = EventInfo::new(event, node_id);
+
+        info.parent = parent;
+        info.position = position;
+        info.from = Some((parent, position));
+        info.removed = self.indexes[start..stop].to_vec();
+        info.before = self.get_value(node_id);
+        info.renamed = Some((key.clone(), key));
+
+        Some(info)
+    }
+
+    /// Complete a captured event once the change is made and emit it
+    pub(crate) fn emit_captured (&mut self, info: Option<EventInfo>) {
+        let mut info = match info {
+            Some(info) => info,
+            None => return
+        };
+
+        match info.event {
+            Event::Remove |
+            Event::Replace => {
+                info.from = None;
+                info.renamed = None;
+            },
+            Event::Update => {
+                let node_id = info.node;
+                let (start, stop) = match self.subtree_range(node_id) {
+                    Ok(range) => range,
+                    Err(_) => return
+                };
+                let descendants = &self.indexes[start + 1..stop];
+                let former = &info.removed[1..];
+
+                info.inserted = descendants.iter().filter(|id| !former.contains(id)).cloned().collect();
+                info.removed = former.iter().filter(|id| !descendants.contains(id)).cloned().collect();
+                info.after = self.get_value(node_id);
+                info.from = None;
+                info.renamed = match info.renamed {
+                    Some((before, _)) => {
+                        let after = unescape(&self.get_node_key(&self.nodes[&node_id]));
+                        if before != after { Some((before, after)) } else { None }
+                    },
+                    None => None
+                };
+            },
+            Event::Move => {
+                if let Ok((parent, position)) = self.sibling_position(info.node) {
+                    info.parent = parent;
+                    info.position = position;
+                }
+
+                info.removed = Vec::new();
+                info.before = None;
+                info.renamed = None;
+            },
+            _ => {}
+        }
+
+        self.emit(info);
+    }
+
+    /// Emit an insertion event per top level node created in a parent
+    pub(crate) fn emit_inserted (&mut self, parent_id: u64, created: &[u64]) {
+        if !self.listening() {
+            return;
+        }
+
+        let roots: Vec<u64> = created.iter().filter(|id| self.nodes.get(id).map(|n| n.parent == parent_id).unwrap_or(false)).cloned().collect();
+        let mut events = Vec::new();
+
+        for root in roots {
+            let (start, stop) = match self.subtree_range(root) {
+                Ok(range) => range,
+                Err(_) => continue
+            };
+            let mut info = EventInfo::new(Event::Insert, root);
+
+            info.parent = parent_id;
+            info.position = self.nodes[&parent_id].childs.iter().position(|id| *id == root).unwrap_or(0);
+            info.inserted = self.indexes[start..stop].to_vec();
+            info.after = self.get_value(root);
+
+            events.push(info);
+        }
+
+        self.emit_all(events);
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Event };
+
+
+/// Journaled change, holding what is needed to apply and revert it
+#[derive(Clone, Debug)]
+pub(crate) enum Record {
+    /// Entries inserted in a parent, with the ids of all the inserted nodes
+    Insert { parent: u64, position: usize, text: Vec<char>, ids: Vec<u64> },
+    /// Entry removed from a parent, with the ids of the removed nodes
+    Remove { parent: u64, position: usize, text: Vec<char>, ids: Vec<u64> },
+    /// Node value replaced, with the ids of the node descendants before and after
+    Value { node: u64, before: (Vec<char>, Vec<u64>), after: (Vec<char>, Vec<u64>) },
+    /// Node key renamed
+    Key { node: u64, before: String, after: String },
+    /// Node moved from a parent position to another
+    Move { node: u64, from: (u64, usize), to: (u64, usize) }
+}
+
+/// Undo and redo stacks of an hson
+#[derive(Clone, Debug)]
+pub(crate) struct History {
+    undo: VecDeque<Vec<Record>>,
+    redo: Vec<Vec<Record>>,
+    limit: usize,
+    group: Option<Vec<Record>>,
+    replaying: bool
+}
+
+impl History {
+    fn new (limit: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+            group: None,
+            replaying: false
+        }
+    }
+
+    /// Journal a change, either in the current group or as its own undo step
+    pub(crate) fn record (&mut self, record: Record) {
+        if self.replaying {
+            return;
+        }
+
+        match self.group {
+            Some(ref mut group) => group.push(record),
+            None => self.push_step(vec![record])
+        }
+    }
+
+    /// Start grouping the following changes into a single undo step
+    pub(crate) fn begin_group (&mut self) {
+        self.group = Some(Vec::new());
+    }
+
+    /// Number of changes in the current group
+    pub(crate) fn group_len (&self) -> usize {
+        self.group.as_ref().map(|group| group.len()).unwrap_or(0)
+    }
+
+    /// Drop the changes of the current group recorded after the provided length
+    pub(crate) fn truncate_group (&mut self, len: usize) {
+        if let Some(ref mut group) = self.group {
+            group.truncate(len);
+        }
+    }
+
+    /// Turn the current group into an undo step, or drop it
+    pub(crate) fn end_group (&mut self, keep: bool) {
+        if let Some(group) = self.group.take() {
+            if keep {
+                self.push_step(group);
+            }
+        }
+    }
+
+    fn push_step (&mut self, step: Vec<Record>) {
+        if step.is_empty() {
+            return;
+        }
+
+        self.undo.push_back(step);
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+
+        self.redo.clear();
+    }
+}
+
+
+impl Hson {
+    /// Start journaling the document changes, keeping at most `limit` undo steps.
+    /// Each operation is an undo step, operations applied in a transaction form a single step
+    pub fn enable_history (&mut self, limit: usize) {
+        match self.history {
+            Some(ref mut history) => {
+                history.limit = limit;
+                while history.undo.len() > limit {
+                    history.undo.pop_front();
+                }
+            },
+            None => self.history = Some(History::new(limit))
+        }
+    }
+
+    /// Stop journaling and drop the existing history
+    pub fn disable_history (&mut self) {
+        self.history = None;
+    }
+
+    /// Drop the existing history, journaling goes on
+    pub fn clear_history (&mut self) {
+        if let Some(ref mut history) = self.history {
+            history.undo.clear();
+            history.redo.clear();
+        }
+    }
+
+    /// Is there a step to undo
+    pub fn can_undo (&self) -> bool {
+        self.history.as_ref().map(|history| !history.undo.is_empty()).unwrap_or(false)
+    }
+
+    /// Is there a step to redo
+    pub fn can_redo (&self) -> bool {
+        self.history.as_ref().map(|history| !history.redo.is_empty()).unwrap_or(false)
+    }
+
+    /// Revert the last undo step. Returns false if there was nothing to undo.
+    /// Steps cannot be undone during a transaction
+    pub fn undo (&mut self) -> Result<bool, Error> {
+        self.outside_transaction()?;
+
+        let step = match self.history.as_mut().and_then(|history| history.undo.pop_back()) {
+            Some(step) => step,
+            None => return Ok(false)
+        };
+
+        match self.replay(&step, true) {
+            Ok(()) => {
+                if let Some(ref mut history) = self.history {
+                    history.redo.push(step);
+                }
+
+                Ok(true)
+            },
+            Err(e) => {
+                if let Some(ref mut history) = self.history {
+                    history.undo.push_back(step);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Apply again the last undone step. Returns false if there was nothing to redo.
+    /// Steps cannot be redone during a transaction
+    pub fn redo (&mut self) -> Result<bool, Error> {
+        self.outside_transaction()?;
+
+        let step = match self.history.as_mut().and_then(|history| history.redo.pop()) {
+            Some(step) => step,
+            None => return Ok(false)
+        };
+
+        match self.replay(&step, false) {
+            Ok(()) => {
+                if let Some(ref mut history) = self.history {
+                    history.undo.push_back(step);
+                }
+
+                Ok(true)
+            },
+            Err(e) => {
+                if let Some(ref mut history) = self.history {
+                    history.redo.push(step);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// The undo and redo stacks are not part of a transaction state, they cannot change during one
+    fn outside_transaction (&self) -> Result<(), Error> {
+        if self.pending.is_some() {
+            let e = Error::new(ErrorKind::Other, "Cannot undo or redo during a transaction");
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Apply or revert a step atomically without journaling it
+    fn replay (&mut self, step: &[Record], revert: bool) -> Result<(), Error> {
+        if let Some(ref mut history) = self.history {
+            history.replaying = true;
+        }
+
+        let result = self.transaction(|hson| {
+            if revert {
+                for record in step.iter().rev() {
+                    hson.revert_record(record)?;
+                }
+            } else {
+                for record in step {
+                    hson.apply_record(record)?;
+                }
+            }
+
+            Ok(())
+        });
+
+        if let Some(ref mut history) = self.history {
+            history.replaying = false;
+        }
+
+        result
+    }
+
+    fn apply_record (&mut self, record: &Record) -> Result<(), Error> {
+        match record {
+            Record::Insert { parent, position, text, ids } => {
+                self.write_entries(*parent, *position, text, ids, None)?;
+                self.emit_inserted(*parent, ids);
+            },
+            Record::Remove { ids, .. } => {
+                let info = self.capture(Event::Remove, ids[0]);
+                self.erase(ids[0])?;
+                self.emit_captured(info);
+            },
+            Record::Value { node, after, .. } => {
+                let info = self.capture(Event::Update, *node);
+                self.write_value(*node, &after.0, &after.1)?;
+                self.emit_captured(info);
+            },
+            Record::Key { node, after, .. } => {
+                let info = self.capture(Event::Update, *node);
+                self.write_key(*node, after)?;
+                self.emit_captured(info);
+            },
+            Record::Move { node, to, .. } => {
+                let info = self.capture(Event::Move, *node);
+                self.relocate(*node, to.0, to.1)?;
+                self.emit_captured(info);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn revert_record (&mut self, record: &Record) -> Result<(), Error> {
+        match record {
+            Record::Insert { parent, ids, .. } => {
+                let roots: Vec<u64> = ids.iter()
+                    .filter(|id| self.nodes.get(id).map(|n| n.parent == *parent).unwrap_or(false))
+                    .cloned()
+                    .collect();
+
+                for id in roots {
+                    let info = self.capture(Event::Remove, id);
+                    self.erase(id)?;
+                    self.emit_captured(info);
+                }
+            },
+            Record::Remove { parent, position, text, ids } => {
+                self.write_entries(*parent, *position, text, ids, None)?;
+                self.emit_inserted(*parent, ids);
+            },
+            Record::Value { node, before, .. } => {
+                let info = self.capture(Event::Update, *node);
+                self.write_value(*node, &before.0, &before.1)?;
+                self.emit_captured(info);
+            },
+            Record::Key { node, before, .. } => {
+                let info = self.capture(Event::Update, *node);
+                self.write_key(*node, before)?;
+                self.emit_captured(info);
+            },
+            Record::Move { node, from, .. } => {
+                let info = self.capture(Event::Move, *node);
+                self.relocate(*node, from.0, from.1)?;
+                self.emit_captured(info);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::Mutex;
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Kind, Value, SubscriptionId, unescape };
+
+
+/// Entries about to be inserted in a parent, array elements have an empty key
+#[derive(PartialEq, Clone, Debug)]
+pub struct Insertion {
+    pub parent: u64,
+    pub position: usize,
+    pub entries: Vec<(String, Value)>
+}
+
+/// Key and value a node is about to take, the unchanged one is the current one
+#[derive(PartialEq, Clone, Debug)]
+pub struct Modification {
+    pub node: u64,
+    pub key: String,
+    pub value: Value
+}
+
+/// Check called before an insertion, an error cancels it
+pub type InsertHook = Box<dyn FnMut(&Hson, &Insertion) -> Result<(), Error> + Send>;
+
+/// Check called before a node removal, an error cancels it
+pub type RemoveHook = Box<dyn FnMut(&Hson, u64) -> Result<(), Error> + Send>;
+
+/// Check called before a node key or value update, an error cancels it
+pub type UpdateHook = Box<dyn FnMut(&Hson, &Modification) -> Result<(), Error> + Send>;
+
+/// Registered hooks
+#[derive(Default)]
+pub(crate) struct Hooks {
+    insert: Vec<(SubscriptionId, Mutex<InsertHook>)>,
+    remove: Vec<(SubscriptionId, Mutex<RemoveHook>)>,
+    update: Vec<(SubscriptionId, Mutex<UpdateHook>)>
+}
+
+impl Hooks {
+    /// Remove a hook, returns false if it was not registered
+    pub(crate) fn remove (&mut self, id: SubscriptionId) -> bool {
+        let l = self.insert.len() + self.remove.len() + self.update.len();
+        self.insert.retain(|(uid, _)| *uid != id);
+        self.remove.retain(|(uid, _)| *uid != id);
+        self.update.retain(|(uid, _)| *uid != id);
+
+        self.insert.len() + self.remove.len() + self.update.len() != l
+    }
+}
+
+
+impl Hson {
+    /// Add a check run by `Ops` before inserting nodes, moved and copied nodes included.
+    /// The hook can read the document, an error is returned by the operation which is cancelled.
+    /// For a replacement, the hook runs once the replaced node is taken out
+    pub fn before_insert (&mut self, hook: InsertHook) -> SubscriptionId {
+        let id = self.next_subscription();
+        self.hooks.insert.push((id, Mutex::new(hook)));
+
+        id
+    }
+
+    /// Add a check run by `Ops` before removing nodes, moved and replaced nodes included
+    pub fn before_remove (&mut self, hook: RemoveHook) -> SubscriptionId {
+        let id = self.next_subscription();
+        self.hooks.remove.push((id, Mutex::new(hook)));
+
+        id
+    }
+
+    /// Add a check run by `Ops` before updating a node key or value
+    pub fn before_update (&mut self, hook: UpdateHook) -> SubscriptionId {
+        let id = self.next_subscription();
+        self.hooks.update.push((id, Mutex::new(hook)));
+
+        id
+    }
+
+    /// Run the insertion hooks, entries are only built when there are hooks
+    pub(crate) fn check_insert<F> (&self, parent: u64, position: usize, entries: F) -> Result<(), Error>
+        where F: FnOnce() -> Vec<(String, Value)> {
+        if self.hooks.insert.is_empty() {
+            return Ok(());
+        }
+
+        let insertion = Insertion { parent, position, entries: entries() };
+        for (_, hook) in &self.hooks.insert {
+            if let Ok(mut hook) = hook.lock() {
+                hook(self, &insertion)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the removal hooks
+    pub(crate) fn check_remove (&self, node_id: u64) -> Result<(), Error> {
+        for (_, hook) in &self.hooks.remove {
+            if let Ok(mut hook) = hook.lock() {
+                hook(self, node_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the update hooks, a missing key or value is the current one.
+    /// The insertion hooks get the childs a new value creates
+    pub(crate) fn check_update (&self, node_id: u64, key: Option<&str>, value: Option<&Value>) -> Result<(), Error> {
+        let node = match self.nodes.get(&node_id) {
+            Some(node) => node,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                return Err(e);
+            }
+        };
+
+        if let Some(value) = value {
+            if value.has_childs() {
+                self.check_insert(node_id, 0, || value_entries(value))?;
+            }
+        }
+
+        if self.hooks.update.is_empty() {
+            return Ok(());
+        }
+
+        let modification = Modification {
+            node: node_id,
+            key: match key {
+                Some(key) => key.to_string(),
+                None => unescape(&self.get_node_key(node))
+            },
+            value: match value {
+                Some(value) => value.clone(),
+                None => self.get_value(node_id).unwrap_or(Value::Null)
+            }
+        };
+        for (_, hook) in &self.hooks.update {
+            if let Ok(mut hook) = hook.lock() {
+                hook(self, &modification)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Root entries of a document, as entries to insert
+    pub(crate) fn root_entries (&self) -> Vec<(String, Value)> {
+        match self.indexes.first().and_then(|root| self.get_value(*root)) {
+            Some(Value::Node(entries)) => entries,
+            _ => Vec::new()
+        }
+    }
+
+    /// A node as an entry to insert in a parent
+    pub(crate) fn node_entry (&self, node_id: u64, parent_id: u64) -> Vec<(String, Value)> {
+        let in_array = self.nodes.get(&parent_id).map(|parent| parent.kind == Kind::Array).unwrap_or(false);
+        let key = match self.nodes.get(&node_id) {
+            Some(node) if !in_array => unescape(&self.get_node_key(node)),
+            _ => String::new()
+        };
+
+        vec![(key, self.get_value(node_id).unwrap_or(Value::Null))]
+    }
+}
+
+/// Childs of a node or array value, as entries to insert
+fn value_entries (value: &Value) -> Vec<(String, Value)> {
+    match value {
+        Value::Node(entries) => entries.clone(),
+        Value::Array(values) => values.iter().map(|v| (String::new(), v.clone())).collect(),
+        _ => Vec::new()
+    }
+}
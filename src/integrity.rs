@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Kind, COMMA };
+
+
+/// Mismatch between the nodes structures and the data they are derived from
+#[derive(PartialEq, Clone, Debug)]
+pub enum IntegrityIssue {
+    /// The data cannot be parsed
+    InvalidData(String),
+    /// Number of nodes described by the data and number of indexed nodes differ
+    NodeCount { expected: usize, found: usize },
+    /// Indexed id without node
+    MissingNode(u64),
+    /// Node absent from the indexes
+    UnindexedNode(u64),
+    Kind { node: u64, expected: Kind, found: Kind },
+    Root { node: u64, expected: bool, found: bool },
+    KeySpan { node: u64, expected: [usize; 2], found: [usize; 2] },
+    ValueSpan { node: u64, expected: [usize; 2], found: [usize; 2] },
+    Parent { node: u64, expected: u64, found: u64 },
+    Childs { node: u64, expected: Vec<u64>, found: Vec<u64> },
+    Instance { node: u64, expected: u64, found: u64 },
+    Instances { expected: u64, found: u64 },
+    /// Keyed node in an array, or node without key in an object
+    Entry { node: u64, parent: u64, keyed: bool },
+    /// Chars other than a single comma between a node entry and its siblings,
+    /// or any char between the entry and its parent delimiters
+    Separator { node: u64 },
+    /// Ids cached for a key
+    Cache { key: String, expected: Vec<u64>, found: Vec<u64> }
+}
+
+impl fmt::Display for IntegrityIssue {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntegrityIssue::InvalidData(e) => write!(f, "invalid data: {}", e),
+            IntegrityIssue::NodeCount { expected, found } => write!(f, "expected {} nodes, found {}", expected, found),
+            IntegrityIssue::MissingNode(id) => write!(f, "indexed node {} does not exist", id),
+            IntegrityIssue::UnindexedNode(id) => write!(f, "node {} is not indexed", id),
+            IntegrityIssue::Kind { node, expected, found } => write!(f, "node {} kind is {:?}, expected {:?}", node, found, expected),
+            IntegrityIssue::Root { node, expected, found } => write!(f, "node {} root flag is {}, expected {}", node, found, expected),
+            IntegrityIssue::KeySpan { node, expected, found } => write!(f, "node {} key is at {:?}, expected {:?}", node, found, expected),
+            IntegrityIssue::ValueSpan { node, expected, found } => write!(f, "node {} value is at {:?}, expected {:?}", node, found, expected),
+            IntegrityIssue::Parent { node, expected, found } => write!(f, "node {} parent is {}, expected {}", node, found, expected),
+            IntegrityIssue::Childs { node, expected, found } => write!(f, "node {} childs are {:?}, expected {:?}", node, found, expected),
+            IntegrityIssue::Instance { node, expected, found } => write!(f, "node {} instance is {}, expected {}", node, found, expected),
+            IntegrityIssue::Instances { expected, found } => write!(f, "instances count is {}, expected {}", found, expected),
+            IntegrityIssue::Entry { node, parent, keyed: true } => write!(f, "node {} has a key in array {}", node, parent),
+            IntegrityIssue::Entry { node, parent, keyed: false } => write!(f, "node {} has no key in object {}", node, parent),
+            IntegrityIssue::Separator { node } => write!(f, "node {} is not separated from its siblings by single commas", node),
+            IntegrityIssue::Cache { key, expected, found } => write!(f, "key `{}` caches {:?}, expected {:?}", key, found, expected)
+        }
+    }
+}
+
+
+impl Hson {
+    /// Parse the data again and compare the result with the existing nodes, indexes,
+    /// instances and cache. Nodes are matched by document order.
+    /// Objects must only hold keyed entries and arrays elements without key, separated by commas
+    pub fn check_integrity (&self) -> Result<(), Vec<IntegrityIssue>> {
+        let mut issues = Vec::new();
+        let mut fresh = Hson::new();
+
+        if let Err(e) = fresh.parse(&self.stringify()) {
+            issues.push(IntegrityIssue::InvalidData(e.to_string()));
+            return Err(issues);
+        }
+
+        for id in &self.indexes {
+            if !self.nodes.contains_key(id) {
+                issues.push(IntegrityIssue::MissingNode(*id));
+            }
+        }
+
+        for id in self.nodes.keys() {
+            if !self.indexes.contains(id) {
+                issues.push(IntegrityIssue::UnindexedNode(*id));
+            }
+        }
+
+        if fresh.indexes.len() != self.indexes.len() {
+            issues.push(IntegrityIssue::NodeCount { expected: fresh.indexes.len(), found: self.indexes.len() });
+        }
+
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+
+        let ids: HashMap<u64, u64> = fresh.indexes.iter().cloned().zip(self.indexes.iter().cloned()).collect();
+        let mut cache: HashMap<String, Vec<u64>> = HashMap::new();
+
+        for (i, (fresh_id, id)) in fresh.indexes.iter().zip(self.indexes.iter()).enumerate() {
+            let expected = &fresh.nodes[fresh_id];
+            let node = &self.nodes[id];
+
+            if node.kind != expected.kind {
+                issues.push(IntegrityIssue::Kind { node: *id, expected: expected.kind.clone(), found: node.kind.clone() });
+            }
+
+            if node.root != expected.root {
+                issues.push(IntegrityIssue::Root { node: *id, expected: expected.root, found: node.root });
+            }
+
+            if node.key != expected.key {
+                issues.push(IntegrityIssue::KeySpan { node: *id, expected: expected.key, found: node.key });
+            }
+
+            if node.value != expected.value {
+                issues.push(IntegrityIssue::ValueSpan { node: *id, expected: expected.value, found: node.value });
+            }
+
+            let parent = if expected.root { 0 } else { ids[&expected.parent] };
+            if node.parent != parent {
+                issues.push(IntegrityIssue::Parent { node: *id, expected: parent, found: node.parent });
+            }
+
+            let childs: Vec<u64> = expected.childs.iter().map(|c| ids[c]).collect();
+            if node.childs != childs {
+                issues.push(IntegrityIssue::Childs { node: *id, expected: childs, found: node.childs.clone() });
+            }
+
+            if expected.kind == Kind::Node || expected.kind == Kind::Array {
+                let mut end = fresh.value_start(expected) + 1;
+
+                for (j, uid) in expected.childs.iter().enumerate() {
+                    let child = &fresh.nodes[uid];
+                    let keyed = child.key != [0, 0];
+                    if keyed != (expected.kind == Kind::Node) {
+                        issues.push(IntegrityIssue::Entry { node: ids[uid], parent: *id, keyed });
+                    }
+
+                    let start = fresh.entry_start(child);
+                    let separator: &[char] = if j == 0 { &[] } else { &[COMMA] };
+                    if start < end || fresh.data[end..start] != *separator {
+                        issues.push(IntegrityIssue::Separator { node: ids[uid] });
+                    }
+
+                    end = fresh.value_end(child);
+                }
+
+                if end != fresh.value_end(expected) - 1 {
+                    let last = expected.childs.last().map(|uid| ids[uid]).unwrap_or(*id);
+                    issues.push(IntegrityIssue::Separator { node: last });
+                }
+            }
+
+            if node.instance != i as u64 + 1 {
+                issues.push(IntegrityIssue::Instance { node: *id, expected: i as u64 + 1, found: node.instance });
+            }
+
+            if !expected.root && expected.key != [0, 0] {
+                cache.entry(fresh.get_node_key(expected)).or_default().push(*id);
+            }
+        }
+
+        if self.instances != fresh.instances {
+            issues.push(IntegrityIssue::Instances { expected: fresh.instances, found: self.instances });
+        }
+
+        for (key, expected) in &cache {
+            let found = self.cache.get(key).cloned().unwrap_or_default();
+
+            if &found != expected {
+                issues.push(IntegrityIssue::Cache { key: key.clone(), expected: expected.clone(), found });
+            }
+        }
+
+        for (key, found) in &self.cache {
+            if !cache.contains_key(key) {
+                issues.push(IntegrityIssue::Cache { key: key.clone(), expected: Vec::new(), found: found.clone() });
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    /// Debug mode running `check_integrity` after every operation.
+    /// An operation leaving the document corrupted returns an error
+    pub fn set_integrity_checks (&mut self, enabled: bool) {
+        self.integrity_checks = enabled;
+    }
+
+    /// Check the document integrity if the debug mode is enabled
+    pub(crate) fn checked (&self) -> Result<(), Error> {
+        if !self.integrity_checks {
+            return Ok(());
+        }
+
+        match self.check_integrity() {
+            Ok(()) => Ok(()),
+            Err(issues) => {
+                let messages: Vec<String> = issues.iter().map(|issue| issue.to_string()).collect();
+                let e = Error::new(ErrorKind::InvalidData, format!("Integrity check failed: {}", messages.join(", ")));
+                Err(e)
+            }
+        }
+    }
+}
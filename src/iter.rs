@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::ops::Deref;
+
+use crate::{ Hson, Node, Value };
+
+
+/// Borrowed node along with the document it belongs to
+#[derive(Clone, Copy)]
+pub struct NodeRef<'a> {
+    hson: &'a Hson,
+    node: &'a Node
+}
+
+impl<'a> NodeRef<'a> {
+    pub(crate) fn new (hson: &'a Hson, node_id: u64) -> Option<NodeRef<'a>> {
+        let node = hson.nodes.get(&node_id)?;
+
+        Some(NodeRef { hson, node })
+    }
+
+    /// Document holding the node
+    pub fn hson (&self) -> &'a Hson {
+        self.hson
+    }
+
+    pub fn node (&self) -> &'a Node {
+        self.node
+    }
+
+    /// Raw node key, empty for the root and array elements
+    pub fn key (&self) -> String {
+        if self.node.root {
+            return String::new();
+        }
+
+        self.hson.get_node_key(self.node)
+    }
+
+    /// Typed node value, including its childs
+    pub fn value (&self) -> Option<Value> {
+        self.hson.get_value(self.node.id)
+    }
+
+    pub fn parent (&self) -> Option<NodeRef<'a>> {
+        NodeRef::new(self.hson, self.node.parent)
+    }
+
+    pub fn childs (&self) -> impl Iterator<Item = NodeRef<'a>> + 'a {
+        let hson = self.hson;
+
+        self.node.childs.iter().filter_map(move |uid| NodeRef::new(hson, *uid))
+    }
+
+    /// Number of ancestors, the root depth is 0
+    pub fn depth (&self) -> usize {
+        let mut depth = 0;
+        let mut current = self.node;
+
+        while let Some(parent) = self.hson.nodes.get(&current.parent) {
+            depth += 1;
+            current = parent;
+        }
+
+        depth
+    }
+}
+
+impl<'a> std::fmt::Debug for NodeRef<'a> {
+    fn fmt (&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("NodeRef").field(self.node).finish()
+    }
+}
+
+impl<'a> Deref for NodeRef<'a> {
+    type Target = Node;
+
+    fn deref (&self) -> &Node {
+        self.node
+    }
+}
+
+
+/// Nodes in document order
+pub struct Iter<'a> {
+    hson: &'a Hson,
+    ids: std::slice::Iter<'a, u64>
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = NodeRef<'a>;
+
+    fn next (&mut self) -> Option<NodeRef<'a>> {
+        let id = self.ids.next()?;
+
+        NodeRef::new(self.hson, *id)
+    }
+
+    fn size_hint (&self) -> (usize, Option<usize>) {
+        self.ids.size_hint()
+    }
+}
+
+/// Nodes depth first, each node before its childs
+pub struct Dfs<'a> {
+    hson: &'a Hson,
+    stack: Vec<u64>
+}
+
+impl<'a> Iterator for Dfs<'a> {
+    type Item = NodeRef<'a>;
+
+    fn next (&mut self) -> Option<NodeRef<'a>> {
+        let node = NodeRef::new(self.hson, self.stack.pop()?)?;
+        self.stack.extend(node.childs.iter().rev());
+
+        Some(node)
+    }
+}
+
+/// Nodes breadth first, level by level
+pub struct Bfs<'a> {
+    hson: &'a Hson,
+    queue: VecDeque<u64>
+}
+
+impl<'a> Iterator for Bfs<'a> {
+    type Item = NodeRef<'a>;
+
+    fn next (&mut self) -> Option<NodeRef<'a>> {
+        let node = NodeRef::new(self.hson, self.queue.pop_front()?)?;
+        self.queue.extend(node.childs.iter());
+
+        Some(node)
+    }
+}
+
+/// Nodes ids in document order along with their depth
+pub struct DepthIter<'a> {
+    hson: &'a Hson,
+    ids: std::slice::Iter<'a, u64>,
+    // Ancestors of the next node
+    ancestors: Vec<u64>
+}
+
+impl<'a> Iterator for DepthIter<'a> {
+    type Item = (usize, u64);
+
+    fn next (&mut self) -> Option<(usize, u64)> {
+        let id = *self.ids.next()?;
+        let parent = self.hson.nodes.get(&id)?.parent;
+
+        while let Some(last) = self.ancestors.last() {
+            if *last == parent {
+                break;
+            }
+
+            self.ancestors.pop();
+        }
+
+        let depth = self.ancestors.len();
+        self.ancestors.push(id);
+
+        Some((depth, id))
+    }
+
+    fn size_hint (&self) -> (usize, Option<usize>) {
+        self.ids.size_hint()
+    }
+}
+
+
+impl Hson {
+    /// Iterate over the nodes in document order without consuming the document
+    pub fn iter (&self) -> Iter<'_> {
+        Iter { hson: self, ids: self.indexes.iter() }
+    }
+
+    /// Iterate over the nodes depth first from the root
+    pub fn iter_dfs (&self) -> Dfs<'_> {
+        Dfs { hson: self, stack: self.indexes.first().cloned().into_iter().collect() }
+    }
+
+    /// Iterate over the nodes breadth first from the root
+    pub fn iter_bfs (&self) -> Bfs<'_> {
+        Bfs { hson: self, queue: self.indexes.first().cloned().into_iter().collect() }
+    }
+
+    /// Iterate depth first over a node and its descendants, nothing is yielded for an unknown node
+    pub fn iter_from (&self, node_id: u64) -> Dfs<'_> {
+        let stack = if self.nodes.contains_key(&node_id) { vec![node_id] } else { Vec::new() };
+
+        Dfs { hson: self, stack }
+    }
+
+    /// Iterate over the nodes ids in document order along with their depth, the root depth is 0
+    pub fn iter_with_depth (&self) -> DepthIter<'_> {
+        DepthIter { hson: self, ids: self.indexes.iter(), ancestors: Vec::new() }
+    }
+}
+
+impl<'a> IntoIterator for &'a Hson {
+    type Item = NodeRef<'a>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter (self) -> Iter<'a> {
+        self.iter()
+    }
+}
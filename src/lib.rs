@@ -1,12 +1,61 @@
 #![allow(unused_assignments)]
-
+#![allow(
+    clippy::clone_on_copy,
+    clippy::collapsible_match,
+    clippy::io_other_error,
+    clippy::manual_contains,
+    clippy::needless_borrow,
+    clippy::ptr_arg,
+    clippy::single_char_add_str,
+    clippy::unnecessary_cast,
+    clippy::unnecessary_unwrap,
+    clippy::unused_enumerate_index,
+    clippy::vec_init_then_push
+)]
+
+use std::any::TypeId;
 use std::collections::HashMap;
 use std::vec::Vec;
 use std::iter::FromIterator;
 use std::io::{ ErrorKind, Error };
 
+#[doc(hidden)]
+pub mod macros;
+mod value;
+mod history;
+mod integrity;
+mod merge;
+mod path;
+mod diff;
+mod patch;
+mod replica;
+mod meta;
+mod iter;
+mod visit;
+mod transform;
+mod events;
+mod hooks;
+mod observe;
+
+pub use value::{ Value, escape, unescape };
+use history::{ History, Record };
+use meta::MetaTable;
+pub use integrity::IntegrityIssue;
+pub use merge::{ MergeStrategy, Conflict, merge3 };
+pub use path::{ HsonPath, Segment };
+pub use diff::{ diff, Change };
+pub use patch::{ Patch, PatchOp };
+pub use replica::{ Replica, ReplicaOp, OpId };
+pub use iter::{ NodeRef, Iter, Dfs, Bfs, DepthIter };
+pub use visit::{ Visitor, VisitControl };
+pub use transform::Transform;
+pub use events::{ Event, EventInfo, SubscriptionId, Listener };
+use events::{ Listeners, Emitted };
+pub use hooks::{ Insertion, Modification, InsertHook, RemoveHook, UpdateHook };
+use hooks::Hooks;
+pub use observe::{ ObserveOptions, MutationKind, MutationRecord, Observer };
+use observe::Observers;
 
-type Callback = fn(Event, u64);
 
 const OPEN_CURLY: char = '{';
 const CLOSE_CURLY: char = '}';
@@ -18,14 +67,6 @@ const COLONS: char = ':';
 const COMMA: char = ',';
 const BACKSLASH: char = '\\';
 
-/// Events types
-#[derive(PartialEq, Clone, Debug)]
-pub enum Event {
-    Parse,
-    Insert,
-    Remove
-}
-
 /// Node types
 #[derive(PartialEq, Clone, Debug)]
 pub enum Kind {
@@ -66,7 +107,20 @@ pub struct Vertex {
     pub value: String
 }
 
+/// Nodes created by an insertion
+#[derive(Clone, Debug, PartialEq)]
+pub struct InsertResult {
+    /// Inserted top level nodes ids
+    pub roots: Vec<u64>,
+    /// All inserted nodes ids in document order
+    pub all: Vec<u64>,
+    /// Ids the inserted nodes have in the grafted document, or in the slice parsed on its own
+    /// with `Hson::parse`, mapped to their final ids
+    pub mapping: HashMap<u64, u64>
+}
+
 /// Controls chars
+#[derive(Clone)]
 struct Controls {
     chars: [char; 7],
     curly_brackets: u16,
@@ -74,14 +128,33 @@ struct Controls {
     double_quotes: u16
 }
 
-/// Hson format
+/// Document state restored when a transaction fails
+struct Snapshot {
+    data: Vec<char>,
+    nodes: HashMap<u64, Node>,
+    indexes: Vec<u64>,
+    instances: u64,
+    cache: HashMap<String, Vec<u64>>,
+    meta: HashMap<TypeId, Box<dyn MetaTable>>,
+    id_count: u64,
+    pending: usize,
+    records: usize
+}
+
+/// Hson format. Documents are `Send + Sync`: listeners, hooks and observers are `Send`, metadata values `Send + Sync`
 pub struct Hson {
     data: Vec<char>,
     pub nodes: HashMap<u64, Node>,
     pub indexes: Vec<u64>,
     instances: u64,
     controls: Controls,
-    callback: Option<Callback>,
+    listeners: Listeners,
+    hooks: Hooks,
+    observers: Observers,
+    pending: Option<Vec<Emitted>>,
+    history: Option<History>,
+    integrity_checks: bool,
+    meta: HashMap<TypeId, Box<dyn MetaTable>>,
     cache: HashMap<String, Vec<u64>>,
     id_count: u64,
     iter_count: usize
@@ -90,22 +163,7 @@ pub struct Hson {
 impl Hson {
     /// Create a new hson
     pub fn new () -> Hson {
-        Hson {
-            data: Vec::new(),
-            nodes: HashMap::new(),
-            indexes: Vec::new(),
-            instances: 0,
-            controls: Controls {
-                chars: [OPEN_CURLY, CLOSE_CURLY, OPEN_ARR, CLOSE_ARR, COLONS, DOUBLE_QUOTES, COMMA],
-                curly_brackets: 0,
-                square_brackets: 0,
-                double_quotes: 0
-            },
-            callback: None,
-            cache: HashMap::new(),
-            id_count: 0,
-            iter_count: 0
-        }
+        Hson::new_slice(0, 0)
     }
 
     /// Create a new hson starting instances count with the provided number
@@ -121,7 +179,13 @@ impl Hson {
                 square_brackets: 0,
                 double_quotes: 0
             },
-            callback: None,
+            listeners: Listeners::default(),
+            hooks: Hooks::default(),
+            observers: Observers::default(),
+            pending: None,
+            history: None,
+            integrity_checks: false,
+            meta: HashMap::new(),
             cache: HashMap::new(),
             id_count: start_id,
             iter_count: 0
@@ -318,7 +382,8 @@ impl Hson {
                     }
                 }
 
-                previous = c;
+                // An escaped backslash must not escape the following char
+                previous = if c == BACKSLASH && previous == BACKSLASH { ' ' } else { c };
                 i += 1;
 
                 if i >= l {
@@ -329,8 +394,10 @@ impl Hson {
             self.data = data;
             self.validate()?;
 
-            if let Some(c) = self.callback {
-                c(Event::Parse, self.id_count);
+            if self.listening() {
+                let mut info = EventInfo::new(Event::Parse, self.indexes[0]);
+                info.inserted = self.indexes.clone();
+                self.emit(info);
             }
         }
 
@@ -445,9 +512,50 @@ impl Hson {
         }
     }
 
-    /// Subscribe to events
-    pub fn subscribe (&mut self, callback: Callback) {
-        self.callback = Some(callback);
+    /// Apply a batch of operations atomically.
+    /// If the closure fails the document is restored as it was before the transaction
+    /// and the error is returned. Events are emitted only once the outermost transaction succeeds
+    pub fn transaction<F, T> (&mut self, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Hson) -> Result<T, Error> {
+        let outermost = self.pending.is_none();
+        if outermost {
+            self.pending = Some(Vec::new());
+
+            if let Some(ref mut history) = self.history {
+                history.begin_group();
+            }
+        }
+
+        let snapshot = self.snapshot();
+        match f(self) {
+            Ok(result) => {
+                if outermost {
+                    if let Some(ref mut history) = self.history {
+                        history.end_group(true);
+                    }
+
+                    if let Some(events) = self.pending.take() {
+                        self.deliver(events);
+                    }
+                    self.prune_observers();
+                }
+
+                Ok(result)
+            },
+            Err(e) => {
+                self.restore(snapshot);
+
+                if outermost {
+                    self.pending = None;
+
+                    if let Some(ref mut history) = self.history {
+                        history.end_group(false);
+                    }
+                }
+
+                Err(e)
+            }
+        }
     }
 
     /// Get node clone with its key and value
@@ -472,6 +580,130 @@ impl Hson {
         }
     }
 
+    /// Get a node typed value, including its childs
+    pub fn get_value (&self, node_id: u64) -> Option<Value> {
+        let node = self.nodes.get(&node_id)?;
+
+        let value = match node.kind {
+            Kind::Node => {
+                let mut entries = Vec::new();
+                for uid in &node.childs {
+                    let child = self.nodes.get(uid)?;
+                    entries.push((unescape(&self.get_node_key(child)), self.get_value(*uid)?));
+                }
+
+                Value::Node(entries)
+            },
+            Kind::Array => {
+                let mut values = Vec::new();
+                for uid in &node.childs {
+                    values.push(self.get_value(*uid)?);
+                }
+
+                Value::Array(values)
+            },
+            Kind::Integer => Value::Integer(self.get_node_value(node).parse::<i64>().ok()?),
+            Kind::Float => Value::Float(self.get_node_value(node).parse::<f64>().ok()?),
+            Kind::String => Value::String(unescape(&self.get_node_value(node))),
+            Kind::Bool => Value::Bool(self.get_node_value(node) == "true"),
+            Kind::Null |
+            Kind::Undefined => Value::Null
+        };
+
+        Some(value)
+    }
+
+    /// Independent copy of the document to apply speculative changes on, same as `clone`
+    pub fn fork (&self) -> Hson {
+        self.clone()
+    }
+
+    /// Compare a node of this document with a node of another one, ids and keys are ignored
+    pub fn subtree_eq (&self, node_id: u64, other: &Hson, other_id: u64) -> bool {
+        let (node, other_node) = match (self.nodes.get(&node_id), other.nodes.get(&other_id)) {
+            (Some(node), Some(other_node)) => (node, other_node),
+            _ => return false
+        };
+
+        node.kind == other_node.kind &&
+            self.data[self.value_start(node)..self.value_end(node)] == other.data[other.value_start(other_node)..other.value_end(other_node)]
+    }
+
+    /// Stringify a node value, delimiters included
+    pub fn stringify_node (&self, node_id: u64) -> Option<String> {
+        let node = self.nodes.get(&node_id)?;
+
+        Some(self.data[self.value_start(node)..self.value_end(node)].iter().collect())
+    }
+
+    /// Copy a node and its childs into a new document where it is the only root child.
+    /// Array elements, which have no key, get an empty one
+    pub fn extract (&self, node_id: u64) -> Option<Hson> {
+        let node = self.nodes.get(&node_id)?;
+        let (begin, end) = self.subtree_range(node_id).ok()?;
+        let key: Vec<char> = if node.root || node.key == [0, 0] { Vec::new() } else { self.data[node.key[0]..node.key[1]].to_vec() };
+        let start = self.value_start(node);
+
+        let mut hson = Hson::new();
+        hson.data.push(OPEN_CURLY);
+        hson.data.push(DOUBLE_QUOTES);
+        hson.data.extend_from_slice(&key);
+        hson.data.push(DOUBLE_QUOTES);
+        hson.data.push(COLONS);
+        let delta = hson.data.len() as isize - start as isize;
+        hson.data.extend_from_slice(&self.data[start..self.value_end(node)]);
+        hson.data.push(CLOSE_CURLY);
+
+        hson.id_count = 1;
+        hson.instances = 1;
+        hson.indexes.push(1);
+        hson.nodes.insert(1, Node {
+            root: true,
+            kind: Kind::Node,
+            parent: 0,
+            childs: vec![2],
+            key: [1, 0],
+            value: [0, hson.data.len() - 1],
+            id: 1,
+            opened: false,
+            instance: 1
+        });
+
+        // Ids follow the document order
+        let ids: HashMap<u64, u64> = self.indexes[begin..end].iter().enumerate().map(|(i, id)| (*id, i as u64 + 2)).collect();
+        let shift = |span: [usize; 2]| [(span[0] as isize + delta) as usize, (span[1] as isize + delta) as usize];
+
+        for uid in &self.indexes[begin..end] {
+            let source = &self.nodes[uid];
+            let id = ids[uid];
+            let key = if *uid == node_id { [2, 2 + key.len()] } else if source.key == [0, 0] { [0, 0] } else { shift(source.key) };
+
+            hson.id_count += 1;
+            hson.instances += 1;
+            hson.indexes.push(id);
+            hson.nodes.insert(id, Node {
+                root: false,
+                kind: source.kind.clone(),
+                parent: if *uid == node_id { 1 } else { ids[&source.parent] },
+                childs: source.childs.iter().map(|c| ids[c]).collect(),
+                key,
+                value: if source.root { shift([source.value[0] + 1, source.value[1]]) } else { shift(source.value) },
+                id,
+                opened: false,
+                instance: hson.instances
+            });
+
+            if key != [0, 0] {
+                let key_str: String = hson.data[key[0]..key[1]].iter().collect();
+                hson.cache.entry(key_str).or_default().push(id);
+            }
+        }
+
+        hson.absorb_meta(self.transfer_meta(&ids));
+
+        Some(hson)
+    }
+
 
     /* PRIVATE */
     /// Retrieve a node key position
@@ -485,7 +717,10 @@ impl Hson {
                 return Err(e);
             };
 
-            if data[data_start_pos] == '"' {
+            // Skip the escaped double quotes of the key
+            let escapes = data[..data_start_pos].iter().rev().take_while(|c| **c == BACKSLASH).count();
+
+            if data[data_start_pos] == '"' && escapes % 2 == 0 {
                 k[on_match as usize] = if on_match == 0 { data_start_pos + 1 } else { data_start_pos };
                 on_match -= 1;
             }
@@ -567,6 +802,12 @@ impl Hson {
 
     /// Guess if position is before colons or not. Must be used on opening double quotes
     fn is_before_colons (&self, mut data_start_pos: usize, data: &[char]) -> bool {
+        let mut escaped = false;
+
+        if data_start_pos + 1 < data.len() && data[data_start_pos + 1] == COLONS && data[data_start_pos] == DOUBLE_QUOTES {
+            return true;
+        }
+
         loop {
             data_start_pos += 1;
 
@@ -574,10 +815,12 @@ impl Hson {
                 break;
             }
 
-            if data[data_start_pos] == COLONS && data[data_start_pos - 1] == DOUBLE_QUOTES { return true; }
-
-            if data[data_start_pos] == DOUBLE_QUOTES && data[data_start_pos - 1] != BACKSLASH {
-                return data[data_start_pos + 1] == COLONS;
+            if escaped {
+                escaped = false;
+            } else if data[data_start_pos] == BACKSLASH {
+                escaped = true;
+            } else if data[data_start_pos] == DOUBLE_QUOTES {
+                return data_start_pos + 1 < data.len() && data[data_start_pos + 1] == COLONS;
             }
         }
 
@@ -592,28 +835,6 @@ impl Hson {
         }
     }
 
-    /// Retrieve position of a child node in its parent node
-    fn get_child_position (&self, node_id: u64, parent_id: u64) -> Result<usize, Error> {
-        let childs = self.get_all_childs(parent_id)?;
-        let mut i = 0;
-
-        if !childs.is_empty() {
-            for id in childs {
-                if id == node_id {
-                    return Ok(i)
-                }
-
-                i += 1;
-            }
-
-            let e = Error::new(ErrorKind::InvalidData, format!("{} has no {} node", parent_id, node_id));
-            return Err(e);
-        } else {
-            let e = Error::new(ErrorKind::InvalidData, format!("{} has no children", parent_id));
-            return Err(e);
-        }
-    }
-
     /// Extract the value from a start position
     fn extract_value (&self, data_start_pos: usize, data: &[char]) -> Result<String, Error> {
         let mut n = data_start_pos;
@@ -632,160 +853,6 @@ impl Hson {
         }
     }
 
-    /// Insert hson slice into data
-    fn insert_into_data (&mut self, hson: Hson, start: usize) -> Hson {
-        let mut i = start;
-        let l = hson.data.len() - 2;
-
-        for (j, c) in hson.data.iter().enumerate() {
-            if j > 0 && j <= l {
-                self.data.insert(i, c.clone());
-                i += 1;
-            }
-        }
-
-        hson
-    }
-
-    /// Insert hson slice nodes into existing nodes
-    fn insert_into_nodes (&mut self, parent_id: u64, start_idx: usize, mut insert_pos: usize, mut hson: Hson) -> Hson {
-        let mut root_id = 0;
-
-        for (_i, key) in hson.indexes.iter().enumerate() {
-            if let Some((k, mut node)) = hson.nodes.remove_entry(key) {
-                if node.root {
-                    root_id = node.id;
-                } else {
-                    // Insert the node into its new parent node
-                    if node.parent == root_id {
-                        node.parent = parent_id;
-                        if let Some(n) = self.nodes.get_mut(&parent_id) {
-                            n.childs.insert(insert_pos, node.id);
-                            insert_pos += 1;
-                        }
-                    }
-
-                    // Replace key and value position
-                    if node.key != [0, 0] {
-                        node.key[0] += start_idx - 1;
-                        node.key[1] += start_idx - 1;
-                    }
-
-                    node.value[0] += start_idx - 1;
-                    node.value[1] += start_idx - 1;
-
-                    let idx = node.instance as usize;
-                    self.indexes.insert(idx - 1, *key);
-                    self.nodes.insert(k, node);
-                }
-            }
-        }
-
-        hson
-    }
-
-    /// Insert hson slice nodes into existing cache
-    fn insert_into_cache (&mut self, hson: Hson) -> Hson {
-        let keys = hson.cache.keys();
-        let mut hson_cache_copy = hson.cache.clone();
-
-        for key in keys {
-            if let Some(mut hson_cache) = hson_cache_copy.get_mut(key) {
-                match self.cache.get_mut(key) {
-                    Some(self_cache) => {
-                        self_cache.append(&mut hson_cache);
-
-                        let nodes = self.nodes.clone();
-                        self_cache.sort_by(|a, b| {
-                            let first = &nodes[a];
-                            let second = &nodes[b];
-
-                            first.instance.cmp(&second.instance)
-                        });
-                    },
-                    None => {
-                        self.cache.insert(key.clone(), hson_cache.clone());
-                    }
-                }
-            }
-        }
-
-
-
-        hson
-    }
-
-    /// Right push existing nodes instance, key and value
-    fn right_push_instances (&mut self, start: u64, distance: u64, data_size: usize) -> Result<(), Error> {
-        let l = self.indexes.len();
-        let mut i = 0;
-        let root_id = self.get_root();
-
-        if let Some(node) = self.nodes.get_mut(&root_id) {
-            node.value[1] += data_size;
-        }
-
-        loop {
-            let key = &self.indexes[i];
-            if let Some(n) = self.nodes.get_mut(key) {
-                if n.instance >= start {
-                    n.instance += distance;
-                    n.value[0] += data_size;
-                    n.value[1] += data_size;
-
-                    if n.key != [0, 0] {
-                        n.key[0] += data_size;
-                        n.key[1] += data_size;
-                    }
-                }
-            }
-
-            i += 1;
-            if i >= l {
-                break;
-            }
-        }
-
-        self.instances += distance;
-
-        Ok(())
-    }
-
-    /// Remove a node from data
-    fn remove_from_data (&mut self, begin: usize, end: usize) {
-        self.data.splice(begin..end, vec!());
-    }
-
-    /// Remove a node from nodes
-    fn remove_from_nodes (&mut self, parent_id: u64, node_id: u64) {
-        // Remove the node from its parent childs
-        if let Some(n) = self.nodes.get_mut(&parent_id) {
-            if let Some(i) = n.childs.iter().position(|s| s == &node_id) {
-                n.childs.remove(i);
-            }
-        };
-
-        // Remove the node from the existing indexes
-        if let Some(i) = self.indexes.iter().position(|s| s == &node_id) {
-            self.indexes.remove(i);
-        };
-
-        // Remove all node childs from the existing nodes
-        match self.get_all_childs(node_id) {
-            Ok(childs) => {
-                for child in childs {
-                    self.nodes.remove(&child);
-                    if let Some(i) = self.indexes.iter().position(|s| s == &child) {
-                        self.indexes.remove(i);
-                    };
-                }
-            },
-            Err(_e) => {}
-        };
-
-        self.nodes.remove(&node_id);
-    }
-
     /// Remove a node from the cache
     fn remove_from_cache (&mut self, key: &str, node_id: u64) {
         if let Some(v) = self.cache.get_mut(key) {
@@ -807,82 +874,6 @@ impl Hson {
         }
     }
 
-    /// Left push existing nodes instance, key and value
-    fn left_push_instances (&mut self, start: u64, distance: u64, data_size: usize) -> Result<(), Error> {
-        let l = self.indexes.len();
-        let mut i = 0;
-        let root_id = self.get_root();
-
-        if let Some(node) = self.nodes.get_mut(&root_id) {
-            node.value[1] -= data_size;
-        }
-
-        loop {
-            let key = &self.indexes[i];
-            if let Some(n) = self.nodes.get_mut(key) {
-                if n.instance >= start {
-                    n.instance -= distance;
-                    n.value[0] -= data_size;
-                    n.value[1] -= data_size;
-
-                    if n.key != [0, 0] {
-                        n.key[0] -= data_size;
-                        n.key[1] -= data_size;
-                    }
-                }
-            }
-
-            i += 1;
-            if i >= l {
-                break;
-            }
-        }
-
-        self.instances -= distance;
-
-        Ok(())
-    }
-
-    /// Insert a comma after the provided node and push right key and value positions
-    fn insert_comma (&mut self, node_id: u64, parent_id: u64) {
-        if let Some(node) = self.nodes.get(&node_id) {
-            let mut instance = node.instance;
-            let pos = node.value[1] + 1;
-            let mut i = 0;
-
-            if !node.childs.is_empty() {
-                if let Some(child) = self.nodes.get(&node.childs[node.childs.len() - 1]) {
-                    instance = child.instance;
-                }
-            }
-
-            self.data.insert(pos, ',');
-            loop {
-                let idx = &self.indexes[i];
-                if let Some(n) = self.nodes.get_mut(idx) {
-                    if i == 0 {
-                        n.value[1] += 1;
-                    } else if n.instance > instance {
-                        if n.key != [0, 0] {
-                            n.key[0] += 1;
-                            n.key[1] += 1;
-                        }
-
-                        n.value[0] += 1;
-                        n.value[1] += 1;
-                    } else if n.id == parent_id {
-                        n.value[1] += 1;
-                    }
-                }
-
-                i += 1;
-                if i >= self.indexes.len() {
-                    break;
-                }
-            }
-        };
-    }
-
     /// Recursive method looking for nodes matching the query
     fn retrieve (&mut self, query: Vec<&str>) -> Result<Vec<u64>, Error> {
         let mut results = Vec::new();
@@ -953,7 +944,7 @@ impl Hson {
                 string_array.push(c);
             }
 
-            previous = c;
+            previous = if c == BACKSLASH && previous == BACKSLASH { ' ' } else { c };
         }
 
         string_array
@@ -1003,59 +994,863 @@ impl Hson {
         }
     }
 
-    fn validate (&self) -> Result<(), Error> {
-        for value in self.nodes.values() {
-            if value.opened {
-                let e = Error::new(ErrorKind::InvalidData, format!("Invalid instance `{}`", value.instance));
-                return Err(e);
-            }
+    /// Copy the document state
+    fn snapshot (&self) -> Snapshot {
+        Snapshot {
+            data: self.data.clone(),
+            nodes: self.nodes.clone(),
+            indexes: self.indexes.clone(),
+            instances: self.instances,
+            cache: self.cache.clone(),
+            meta: self.meta.clone(),
+            id_count: self.id_count,
+            pending: self.pending.as_ref().map(|events| events.len()).unwrap_or(0),
+            records: self.history.as_ref().map(|history| history.group_len()).unwrap_or(0)
         }
-
-        Ok(())
     }
-}
 
+    /// Restore a document state
+    fn restore (&mut self, snapshot: Snapshot) {
+        self.data = snapshot.data;
+        self.nodes = snapshot.nodes;
+        self.indexes = snapshot.indexes;
+        self.instances = snapshot.instances;
+        self.cache = snapshot.cache;
+        self.meta = snapshot.meta;
+        self.id_count = snapshot.id_count;
 
-impl Default for Hson {
-    fn default () -> Self {
-        Self::new()
+        if let Some(ref mut events) = self.pending {
+            events.truncate(snapshot.pending);
+        }
+
+        if let Some(ref mut history) = self.history {
+            history.truncate_group(snapshot.records);
+        }
     }
-}
 
+    /// Journal a change if the history is enabled
+    fn record (&mut self, record: Record) {
+        if let Some(ref mut history) = self.history {
+            history.record(record);
+        }
+    }
 
-pub trait Query {
-    fn query (&mut self, q: &str) -> Result<Vec<u64>, Error>;
+    /// Position of the first char of a node value, delimiters included
+    fn value_start (&self, node: &Node) -> usize {
+        if node.root {
+            return node.value[0];
+        }
 
-    fn query_nodes (&mut self, q: &str) -> Result<Vec<&Node>, Error>;
+        match node.kind {
+            Kind::Node |
+            Kind::Array |
+            Kind::String => node.value[0] - 1,
+            _ => node.value[0]
+        }
+    }
 
-    fn query_on (&mut self, node_id: u64, q: &str, recursive: bool) -> Result<Vec<u64>, Error>;
+    /// Position following the last char of a node value, delimiters included
+    fn value_end (&self, node: &Node) -> usize {
+        if node.root {
+            return node.value[1] + 1;
+        }
 
-    fn query_on_nodes (&mut self, node: &Node, q: &str, recursive: bool) -> Result<Vec<&Node>, Error>;
-}
+        match node.kind {
+            Kind::Node |
+            Kind::Array |
+            Kind::String => node.value[1] + 1,
+            _ => node.value[1]
+        }
+    }
 
-impl Query for Hson {
-    /// Public method to query the data
-    fn query (&mut self, q: &str) -> Result<Vec<u64>, Error> {
-        let parts: Vec<&str> = q.split(' ').collect();
-        let results = self.retrieve(parts)?;
+    /// Position of the first char of a node entry, key included
+    fn entry_start (&self, node: &Node) -> usize {
+        if !node.root && node.key != [0, 0] {
+            node.key[0] - 1
+        } else {
+            self.value_start(node)
+        }
+    }
 
-        Ok(results)
+    /// Data range of a node entry along with one of its surrounding commas
+    fn entry_range (&self, node: &Node) -> (usize, usize) {
+        let begin = self.entry_start(node);
+        let end = self.value_end(node);
+
+        if begin > 0 && self.data[begin - 1] == COMMA {
+            (begin - 1, end)
+        } else if end < self.data.len() && self.data[end] == COMMA {
+            (begin, end + 1)
+        } else {
+            (begin, end)
+        }
     }
 
-    /// Same as `query` but return nodes structures instead of their ids
-    fn query_nodes (&mut self, q: &str) -> Result<Vec<&Node>, Error> {
-        let mut results = Vec::new();
-        let parts: Vec<&str> = q.split(' ').collect();
+    /// Insert entries text in a parent node at the provided childs position.
+    /// Inserted nodes take the provided ids or new ones if none are provided.
+    /// When `moved` is set, the node is taken out of its current position first
+    /// and the provided ids must be its own. Returns the new ids
+    fn write_entries (&mut self, parent_id: u64, position: usize, text: &[char], ids: &[u64], moved: Option<u64>) -> Result<Vec<u64>, Error> {
+        let parent = match self.nodes.get(&parent_id) {
+            Some(parent) => parent,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", parent_id));
+                return Err(e);
+            }
+        };
 
-        let ids = self.retrieve(parts)?;
-        for uid in &ids {
-            results.push(&self.nodes[uid]);
+        let (slice, container) = self.parse_entries(parent.kind.clone(), text)?;
+
+        if let Some(moved_id) = moved {
+            let l = parent.childs.iter().filter(|id| **id != moved_id).count();
+            if position > l {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid index {}", position));
+                return Err(e);
+            }
+
+            self.detach(moved_id)?;
         }
 
-        Ok(results)
+        let result = self.splice_document(parent_id, position, &slice, container, ids)?;
+        if moved.is_none() {
+            self.record(Record::Insert { parent: parent_id, position, text: text.to_vec(), ids: result.all.clone() });
+        }
+
+        Ok(result.all)
     }
 
-    /// Same as `query` but constrain the search in the provided node's childs only
+    /// Parse entries text on its own, returns the parsed slice and the node holding the entries.
+    /// Array elements are parsed in an array
+    fn parse_entries (&self, kind: Kind, text: &[char]) -> Result<(Hson, u64), Error> {
+        let mut s = String::with_capacity(text.len() + 6);
+        if kind == Kind::Array {
+            s.push_str("{\"\":[");
+            s.extend(text);
+            s.push_str("]}");
+        } else {
+            s.push(OPEN_CURLY);
+            s.extend(text);
+            s.push(CLOSE_CURLY);
+        }
+
+        let mut slice = Hson::new();
+        slice.parse(&s)?;
+
+        let container = if kind == Kind::Array { slice.indexes[1] } else { slice.indexes[0] };
+
+        Ok((slice, container))
+    }
+
+    /// Clean and check an hson slice, returns the slice parsed on its own
+    fn prepare_slice (&self, data_to_insert: &str) -> Result<Hson, Error> {
+        let t = self.clean(data_to_insert);
+
+        if t.len() < 2 || t[0] != OPEN_CURLY || t[t.len() - 1] != CLOSE_CURLY {
+            let e = Error::new(ErrorKind::InvalidData, "An hson slice must be wrapped in curly brackets");
+            return Err(e);
+        }
+
+        let s: String = t.iter().collect();
+        let mut slice = Hson::new();
+        slice.parse(&s)?;
+
+        Ok(slice)
+    }
+
+    /// Insert a parsed hson slice entries in a parent node
+    fn write_slice (&mut self, parent_id: u64, position: usize, slice: &Hson) -> Result<InsertResult, Error> {
+        let root = slice.indexes[0];
+        let result = self.splice_document(parent_id, position, slice, root, &[])?;
+        let text = slice.data[1..slice.data.len() - 1].to_vec();
+        self.record(Record::Insert { parent: parent_id, position, text, ids: result.all.clone() });
+
+        Ok(result)
+    }
+
+    /// Insert an hson slice entries in a parent node once the hooks accept them
+    fn insert_slice (&mut self, parent_id: u64, position: usize, data_to_insert: &str) -> Result<InsertResult, Error> {
+        let slice = self.prepare_slice(data_to_insert)?;
+        self.check_insert(parent_id, position, || slice.root_entries())?;
+
+        self.write_slice(parent_id, position, &slice)
+    }
+
+    /// Splice the childs of a parsed document node in a parent node without parsing them again.
+    /// Existing spans are shifted and the childs are added with the provided ids or new ones
+    fn splice_document (&mut self, parent_id: u64, position: usize, other: &Hson, container: u64, ids: &[u64]) -> Result<InsertResult, Error> {
+        let parent = match self.nodes.get(&parent_id) {
+            Some(parent) => parent,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", parent_id));
+                return Err(e);
+            }
+        };
+
+        if parent.kind != Kind::Node && parent.kind != Kind::Array {
+            let e = Error::new(ErrorKind::InvalidData, format!("{} cannot have childs", parent_id));
+            return Err(e);
+        }
+
+        if position > parent.childs.len() {
+            let e = Error::new(ErrorKind::InvalidData, format!("Invalid index {}", position));
+            return Err(e);
+        }
+
+        let source = match other.nodes.get(&container) {
+            Some(source) => source,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, "Cannot insert an empty document");
+                return Err(e);
+            }
+        };
+
+        self.check_entries(parent_id, other, container)?;
+
+        let begin = other.value_start(source) + 1;
+        let end = other.value_end(source) - 1;
+        if begin >= end {
+            return Ok(InsertResult { roots: Vec::new(), all: Vec::new(), mapping: HashMap::new() });
+        }
+
+        let text = other.data[begin..end].to_vec();
+        let (start, idx) = self.insert_into_data(parent_id, position, &text)?;
+        let result = self.insert_into_nodes(parent_id, position, (start, idx), (other, container), ids)?;
+        self.insert_into_cache(&result.all);
+
+        Ok(result)
+    }
+
+    /// Check that the childs of a parsed document node can go in a parent,
+    /// keyed entries in an object and array elements in an array
+    fn check_entries (&self, parent_id: u64, other: &Hson, container: u64) -> Result<(), Error> {
+        let parent = match self.nodes.get(&parent_id) {
+            Some(parent) => parent,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", parent_id));
+                return Err(e);
+            }
+        };
+
+        for uid in &other.nodes[&container].childs {
+            let keyed = other.nodes[uid].key != [0, 0];
+
+            if keyed && parent.kind == Kind::Array {
+                let e = Error::new(ErrorKind::InvalidData, format!("Cannot put a keyed node in array {}", parent_id));
+                return Err(e);
+            }
+
+            if !keyed && parent.kind == Kind::Node {
+                let e = Error::new(ErrorKind::InvalidData, format!("Cannot put an array element in node {}", parent_id));
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Insert entries text in the data at a parent childs position along with the comma
+    /// separating them from their siblings. Returns the position of the entries first char
+    /// and the position of their nodes in the indexes
+    fn insert_into_data (&mut self, parent_id: u64, position: usize, text: &[char]) -> Result<(usize, usize), Error> {
+        let parent = &self.nodes[&parent_id];
+        let parent_start = parent.value[0];
+        let mut entries = Vec::with_capacity(text.len() + 1);
+        let (pos, previous) = if parent.childs.is_empty() {
+            entries.extend_from_slice(text);
+            (self.value_start(parent) + 1, parent_id)
+        } else if position == 0 {
+            entries.extend_from_slice(text);
+            entries.push(COMMA);
+            (self.entry_start(&self.nodes[&parent.childs[0]]), parent_id)
+        } else {
+            let previous = parent.childs[position - 1];
+
+            entries.push(COMMA);
+            entries.extend_from_slice(text);
+            (self.value_end(&self.nodes[&previous]), previous)
+        };
+        // Entries nodes go after the previous sibling descendants
+        let idx = match self.indexes.iter().position(|id| id == &previous) {
+            Some(idx) if previous == parent_id => idx + 1,
+            Some(idx) => idx + 1 + self.get_all_childs(previous)?.len(),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", previous));
+                return Err(e);
+            }
+        };
+        let start = if entries[0] == COMMA { pos + 1 } else { pos };
+
+        // The parent opening and the previous sibling end stay before the entries
+        let previous_end = self.nodes[&previous].value[1];
+        let l = entries.len();
+        self.data.splice(pos..pos, entries);
+        self.shift_spans(pos, l as isize);
+        if let Some(node) = self.nodes.get_mut(&parent_id) {
+            node.value[0] = parent_start;
+        }
+        if previous != parent_id && previous_end == pos {
+            if let Some(node) = self.nodes.get_mut(&previous) {
+                node.value[1] = previous_end;
+            }
+        }
+
+        Ok((start, idx))
+    }
+
+    /// Add the descendants of a parsed document node, whose data now starts at `start`,
+    /// at the `idx` position of the indexes. Its childs go at the provided parent childs position
+    fn insert_into_nodes (&mut self, parent_id: u64, position: usize, (start, idx): (usize, usize), (other, container): (&Hson, u64), ids: &[u64]) -> Result<InsertResult, Error> {
+        let (first, stop) = other.subtree_range(container)?;
+        let descendants = &other.indexes[first + 1..stop];
+
+        if !ids.is_empty() && ids.len() != descendants.len() {
+            let e = Error::new(ErrorKind::InvalidData, format!("Expected {} ids, found {}", descendants.len(), ids.len()));
+            return Err(e);
+        }
+
+        let mut mapping = HashMap::new();
+        for (i, uid) in descendants.iter().enumerate() {
+            let id = match ids.get(i) {
+                Some(id) => *id,
+                None => {
+                    self.id_count += 1;
+                    self.id_count
+                }
+            };
+
+            mapping.insert(*uid, id);
+        }
+
+        let origin = other.value_start(&other.nodes[&container]) + 1;
+        let shift = |span: [usize; 2]| [span[0] + start - origin, span[1] + start - origin];
+        let mut roots = Vec::new();
+        let mut all = Vec::with_capacity(descendants.len());
+        for uid in descendants {
+            let source = &other.nodes[uid];
+            let id = mapping[uid];
+            let root_child = source.parent == container;
+
+            if root_child {
+                roots.push(id);
+            }
+
+            all.push(id);
+            self.nodes.insert(id, Node {
+                root: false,
+                kind: source.kind.clone(),
+                parent: if root_child { parent_id } else { mapping[&source.parent] },
+                childs: source.childs.iter().map(|c| mapping[c]).collect(),
+                key: if source.key == [0, 0] { [0, 0] } else { shift(source.key) },
+                value: shift(source.value),
+                id,
+                opened: false,
+                instance: 0
+            });
+        }
+
+        if let Some(node) = self.nodes.get_mut(&parent_id) {
+            node.childs.splice(position..position, roots.clone());
+        }
+
+        self.indexes.splice(idx..idx, all.clone());
+        self.instances += all.len() as u64;
+        for (i, uid) in self.indexes.iter().enumerate().skip(idx) {
+            if let Some(node) = self.nodes.get_mut(uid) {
+                node.instance = i as u64 + 1;
+            }
+        }
+
+        Ok(InsertResult {
+            roots,
+            all,
+            mapping
+        })
+    }
+
+    /// Add inserted nodes keys to the cache
+    fn insert_into_cache (&mut self, ids: &[u64]) {
+        for id in ids {
+            let node = &self.nodes[id];
+            if node.key != [0, 0] {
+                let key = self.get_node_key(node);
+                self.cache_insert(key, *id);
+            }
+        }
+    }
+
+    /// Remove a node entry from the data along with its childs
+    fn erase (&mut self, node_id: u64) -> Result<(), Error> {
+        let node = match self.nodes.get(&node_id) {
+            Some(node) => node,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                return Err(e);
+            }
+        };
+
+        if node.root {
+            let e = Error::new(ErrorKind::InvalidData, "Cannot remove the root node");
+            return Err(e);
+        }
+
+        let text = self.data[self.entry_start(node)..self.value_end(node)].to_vec();
+        let (parent_id, position) = self.sibling_position(node_id)?;
+        let ids = self.detach(node_id)?;
+
+        self.prune_meta();
+        self.prune_observers();
+        self.record(Record::Remove { parent: parent_id, position, text, ids });
+
+        Ok(())
+    }
+
+    /// Take a node entry and its childs out of the document in place, returns their ids
+    fn detach (&mut self, node_id: u64) -> Result<Vec<u64>, Error> {
+        let (begin, end) = self.entry_range(&self.nodes[&node_id]);
+        let (start, stop) = self.subtree_range(node_id)?;
+        let ids = self.indexes[start..stop].to_vec();
+
+        for id in &ids {
+            let node = &self.nodes[id];
+            if node.key != [0, 0] {
+                let key = self.get_node_key(node);
+                self.remove_from_cache(&key, *id);
+            }
+        }
+
+        self.remove_from_data(begin, end);
+        self.remove_from_nodes(node_id, start, stop);
+        self.left_push_instances(start);
+
+        Ok(ids)
+    }
+
+    /// Remove a range from the data and pull the following positions back
+    fn remove_from_data (&mut self, begin: usize, end: usize) {
+        self.data.splice(begin..end, vec!());
+        self.shift_spans(end, begin as isize - end as isize);
+    }
+
+    /// Remove a node and its childs, found between `start` and `stop` in the indexes
+    fn remove_from_nodes (&mut self, node_id: u64, start: usize, stop: usize) {
+        let parent_id = self.nodes[&node_id].parent;
+
+        // Remove the node from its parent childs
+        if let Some(n) = self.nodes.get_mut(&parent_id) {
+            if let Some(i) = n.childs.iter().position(|s| s == &node_id) {
+                n.childs.remove(i);
+            }
+        }
+
+        for id in self.indexes.splice(start..stop, vec!()) {
+            self.nodes.remove(&id);
+        }
+
+        self.instances -= (stop - start) as u64;
+    }
+
+    /// Number again the nodes instances from the provided indexes position
+    fn left_push_instances (&mut self, start: usize) {
+        for (i, uid) in self.indexes.iter().enumerate().skip(start) {
+            if let Some(node) = self.nodes.get_mut(uid) {
+                node.instance = i as u64 + 1;
+            }
+        }
+    }
+
+    /// Insert an hson slice and return the ids of its top level nodes
+    fn insert_relative (&mut self, parent_id: u64, position: usize, data_to_insert: &str) -> Result<Vec<u64>, Error> {
+        let result = self.insert_slice(parent_id, position, data_to_insert)?;
+        self.checked()?;
+
+        self.emit_inserted(parent_id, &result.all);
+
+        Ok(result.roots)
+    }
+
+    /// Retrieve a node parent and its position in the parent childs
+    fn sibling_position (&self, node_id: u64) -> Result<(u64, usize), Error> {
+        match self.nodes.get(&node_id) {
+            Some(node) if node.root => {
+                let e = Error::new(ErrorKind::InvalidData, "The root node has no siblings");
+                Err(e)
+            },
+            Some(node) => {
+                let position = self.nodes[&node.parent].childs.iter().position(|id| id == &node_id).unwrap_or(0);
+
+                Ok((node.parent, position))
+            },
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                Err(e)
+            }
+        }
+    }
+
+    /// Retrieve the elements ids of an array node
+    fn array_elements (&self, array_id: u64) -> Result<&Vec<u64>, Error> {
+        match self.nodes.get(&array_id) {
+            Some(node) if node.kind == Kind::Array => Ok(&node.childs),
+            Some(_) => {
+                let e = Error::new(ErrorKind::InvalidData, format!("{} is not an array", array_id));
+                Err(e)
+            },
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", array_id));
+                Err(e)
+            }
+        }
+    }
+
+    /// Retrieve the id of an array element
+    fn array_element (&self, array_id: u64, idx: usize) -> Result<u64, Error> {
+        match self.array_elements(array_id)?.get(idx) {
+            Some(id) => Ok(*id),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid index {}", idx));
+                Err(e)
+            }
+        }
+    }
+
+    /// Replace a node value, patching the data in place when no childs are involved
+    fn assign_value (&mut self, node_id: u64, value: &Value) -> Result<(), Error> {
+        let node = match self.nodes.get(&node_id) {
+            Some(node) => node.clone(),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                return Err(e);
+            }
+        };
+
+        if node.root {
+            let e = Error::new(ErrorKind::InvalidData, "Cannot set the root node value");
+            return Err(e);
+        }
+
+        let text: Vec<char> = value.to_hson().chars().collect();
+
+        if node.childs.is_empty() && !value.has_childs() {
+            // Leaf to leaf, patch the data and the positions in place
+            let before = self.write_span(node_id, &text, value.kind());
+
+            self.record(Record::Value { node: node_id, before: (before, Vec::new()), after: (text, Vec::new()) });
+        } else {
+            self.write_value(node_id, &text, &[])?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace a node value data in place, its descendants take the provided ids or new ones
+    fn write_value (&mut self, node_id: u64, text: &[char], ids: &[u64]) -> Result<Vec<u64>, Error> {
+        let node = match self.nodes.get(&node_id) {
+            Some(node) => node,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                return Err(e);
+            }
+        };
+
+        let before = self.data[self.value_start(node)..self.value_end(node)].to_vec();
+        let (start, stop) = self.subtree_range(node_id)?;
+        let descendants = self.indexes[start + 1..stop].to_vec();
+
+        // The value is parsed on its own first, an invalid one leaves the document untouched
+        let mut entry = vec![DOUBLE_QUOTES, DOUBLE_QUOTES, COLONS];
+        entry.extend_from_slice(text);
+        let (slice, root) = self.parse_entries(Kind::Node, &entry)?;
+        let source = match slice.nodes[&root].childs.as_slice() {
+            [source] => *source,
+            _ => {
+                let e = Error::new(ErrorKind::InvalidData, "Expected a single value");
+                return Err(e);
+            }
+        };
+        let (first, last) = slice.subtree_range(source)?;
+        if !ids.is_empty() && ids.len() != last - first - 1 {
+            let e = Error::new(ErrorKind::InvalidData, format!("Expected {} ids, found {}", last - first - 1, ids.len()));
+            return Err(e);
+        }
+
+        // Take the former childs out, then write the bare value and splice the new childs in
+        for child in self.nodes[&node_id].childs.clone() {
+            self.detach(child)?;
+        }
+
+        let kind = slice.nodes[&source].kind.clone();
+        let shell: Vec<char> = match kind {
+            Kind::Node => vec![OPEN_CURLY, CLOSE_CURLY],
+            Kind::Array => vec![OPEN_ARR, CLOSE_ARR],
+            _ => text.to_vec()
+        };
+        self.write_span(node_id, &shell, kind);
+
+        let created = if slice.nodes[&source].childs.is_empty() {
+            Vec::new()
+        } else {
+            self.splice_document(node_id, 0, &slice, source, ids)?.all
+        };
+        self.prune_meta();
+        self.prune_observers();
+
+        let after = if ids.is_empty() { created.clone() } else { ids.to_vec() };
+        self.record(Record::Value { node: node_id, before: (before, descendants), after: (text.to_vec(), after) });
+
+        Ok(created)
+    }
+
+    /// Replace the data of a node value without childs and update its kind and positions,
+    /// returns the former data
+    fn write_span (&mut self, node_id: u64, text: &[char], kind: Kind) -> Vec<char> {
+        let node = &self.nodes[&node_id];
+        let begin = self.value_start(node);
+        let end = self.value_end(node);
+        let l = text.len();
+        let delta = l as isize - (end - begin) as isize;
+        let before = self.data.splice(begin..end, text.to_vec()).collect();
+
+        self.shift_spans(end, delta);
+
+        if let Some(n) = self.nodes.get_mut(&node_id) {
+            n.value = match kind {
+                Kind::Node |
+                Kind::Array |
+                Kind::String => [begin + 1, begin + l - 1],
+                _ => [begin, begin + l]
+            };
+            n.kind = kind;
+        }
+
+        before
+    }
+
+    /// Rename a node key
+    fn write_key (&mut self, node_id: u64, key: &str) -> Result<(), Error> {
+        let node = match self.nodes.get(&node_id) {
+            Some(node) => node.clone(),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                return Err(e);
+            }
+        };
+
+        if node.root || node.key == [0, 0] {
+            let e = Error::new(ErrorKind::InvalidData, format!("{} has no key", node_id));
+            return Err(e);
+        }
+
+        let old_key = self.get_node_key(&node);
+        let text: Vec<char> = escape(key).chars().collect();
+        let delta = text.len() as isize - (node.key[1] - node.key[0]) as isize;
+
+        self.data.splice(node.key[0]..node.key[1], text);
+        self.shift_spans(node.key[1], delta);
+        self.remove_from_cache(&old_key, node_id);
+        self.cache_insert(escape(key), node_id);
+        self.record(Record::Key { node: node_id, before: unescape(&old_key), after: key.to_string() });
+
+        Ok(())
+    }
+
+    /// Move a node and its childs to the provided position of a new parent
+    fn relocate (&mut self, node_id: u64, parent_id: u64, position: usize) -> Result<(), Error> {
+        let text = self.movable_entry(node_id, parent_id)?;
+
+        if node_id == parent_id || self.is_descendant(node_id, parent_id) {
+            let e = Error::new(ErrorKind::InvalidData, format!("Cannot move {} into itself", node_id));
+            return Err(e);
+        }
+
+        let from = self.sibling_position(node_id)?;
+        let (start, stop) = self.subtree_range(node_id)?;
+        let ids = self.indexes[start..stop].to_vec();
+        self.write_entries(parent_id, position, &text, &ids, Some(node_id))?;
+        self.record(Record::Move { node: node_id, from, to: (parent_id, position) });
+
+        Ok(())
+    }
+
+    /// Retrieve a node entry to be moved or copied into the provided parent
+    fn movable_entry (&self, node_id: u64, parent_id: u64) -> Result<Vec<char>, Error> {
+        let node = match self.nodes.get(&node_id) {
+            Some(node) => node,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                return Err(e);
+            }
+        };
+
+        if node.root {
+            let e = Error::new(ErrorKind::InvalidData, "Cannot move the root node");
+            return Err(e);
+        }
+
+        let keyed = node.key != [0, 0];
+        match self.nodes.get(&parent_id) {
+            Some(parent) if keyed && parent.kind == Kind::Array => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Cannot put a keyed node in array {}", parent_id));
+                Err(e)
+            },
+            Some(parent) if !keyed && parent.kind == Kind::Node => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Cannot put an array element in node {}", parent_id));
+                Err(e)
+            },
+            Some(_) => Ok(self.data[self.entry_start(node)..self.value_end(node)].to_vec()),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", parent_id));
+                Err(e)
+            }
+        }
+    }
+
+    /// Range of a node and its descendants in the indexes
+    fn subtree_range (&self, node_id: u64) -> Result<(usize, usize), Error> {
+        match self.indexes.iter().position(|id| id == &node_id) {
+            Some(idx) => {
+                let childs = self.get_all_childs(node_id)?;
+
+                Ok((idx, idx + childs.len() + 1))
+            },
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+                Err(e)
+            }
+        }
+    }
+
+    /// Move every key and value position starting from the provided position
+    fn shift_spans (&mut self, from: usize, delta: isize) {
+        for node in self.nodes.values_mut() {
+            if !node.root && node.key != [0, 0] {
+                for pos in node.key.iter_mut() {
+                    if *pos >= from {
+                        *pos = (*pos as isize + delta) as usize;
+                    }
+                }
+            }
+
+            for pos in node.value.iter_mut() {
+                if *pos >= from {
+                    *pos = (*pos as isize + delta) as usize;
+                }
+            }
+        }
+    }
+
+    /// Insert a node id into the cache keeping the cached ids in instance order
+    fn cache_insert (&mut self, key: String, node_id: u64) {
+        let instance = match self.nodes.get(&node_id) {
+            Some(node) => node.instance,
+            None => return
+        };
+        let nodes = &self.nodes;
+        let ids = self.cache.entry(key).or_default();
+        let pos = ids.iter()
+            .position(|id| nodes.get(id).map(|n| n.instance > instance).unwrap_or(false))
+            .unwrap_or(ids.len());
+
+        ids.insert(pos, node_id);
+    }
+
+    fn validate (&self) -> Result<(), Error> {
+        for value in self.nodes.values() {
+            if value.opened {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid instance `{}`", value.instance));
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Default for Hson {
+    fn default () -> Self {
+        Self::new()
+    }
+}
+
+/// The copy has no events listener, hook nor observer, no history and no pending transaction
+impl Clone for Hson {
+    fn clone (&self) -> Self {
+        Hson {
+            data: self.data.clone(),
+            nodes: self.nodes.clone(),
+            indexes: self.indexes.clone(),
+            instances: self.instances,
+            controls: self.controls.clone(),
+            listeners: Listeners::default(),
+            hooks: Hooks::default(),
+            observers: Observers::default(),
+            pending: None,
+            history: None,
+            integrity_checks: self.integrity_checks,
+            meta: self.meta.clone(),
+            cache: self.cache.clone(),
+            id_count: self.id_count,
+            iter_count: self.iter_count
+        }
+    }
+}
+
+/// Documents are equal if they hold the same data, whatever their nodes ids
+impl PartialEq for Hson {
+    fn eq (&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl std::fmt::Debug for Hson {
+    fn fmt (&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Hson")
+            .field("data", &self.stringify())
+            .field("nodes", &self.nodes.len())
+            .finish()
+    }
+}
+
+impl std::fmt::Display for Hson {
+    fn fmt (&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.stringify())
+    }
+}
+
+
+pub trait Query {
+    fn query (&mut self, q: &str) -> Result<Vec<u64>, Error>;
+
+    fn query_nodes (&mut self, q: &str) -> Result<Vec<&Node>, Error>;
+
+    fn query_on (&mut self, node_id: u64, q: &str, recursive: bool) -> Result<Vec<u64>, Error>;
+
+    fn query_on_nodes (&mut self, node: &Node, q: &str, recursive: bool) -> Result<Vec<&Node>, Error>;
+}
+
+impl Query for Hson {
+    /// Public method to query the data
+    fn query (&mut self, q: &str) -> Result<Vec<u64>, Error> {
+        let parts: Vec<&str> = q.split(' ').collect();
+        let results = self.retrieve(parts)?;
+
+        Ok(results)
+    }
+
+    /// Same as `query` but return nodes structures instead of their ids
+    fn query_nodes (&mut self, q: &str) -> Result<Vec<&Node>, Error> {
+        let mut results = Vec::new();
+        let parts: Vec<&str> = q.split(' ').collect();
+
+        let ids = self.retrieve(parts)?;
+        for uid in &ids {
+            results.push(&self.nodes[uid]);
+        }
+
+        Ok(results)
+    }
+
+    /// Same as `query` but constrain the search in the provided node's childs only
     fn query_on (&mut self, node_id: u64, q: &str, recursive: bool) -> Result<Vec<u64>, Error> {
         let mut results = Vec::new();
         let parts: Vec<&str> = q.split(' ').collect();
@@ -1100,190 +1895,221 @@ impl Query for Hson {
 
 
 pub trait Ops {
-    fn insert (&mut self, node_id: u64, insert_pos: usize, data_to_insert: &str) -> Result<(), Error>;
+    fn insert (&mut self, node_id: u64, insert_pos: usize, data_to_insert: &str) -> Result<InsertResult, Error>;
+
+    fn append_child (&mut self, node_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error>;
+
+    fn prepend_child (&mut self, node_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error>;
+
+    fn insert_before (&mut self, sibling_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error>;
+
+    fn insert_after (&mut self, sibling_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error>;
+
+    fn graft (&mut self, parent_id: u64, position: usize, other: Hson) -> Result<InsertResult, Error>;
 
     fn remove (&mut self, node_id: u64) -> Result<(), Error>;
 
-    fn replace (&mut self, node_id: u64, data_to_insert: &str) -> Result<(), Error>;
-}
+    fn replace (&mut self, node_id: u64, data_to_insert: &str) -> Result<InsertResult, Error>;
 
-impl Ops for Hson {
-    /// Insert an hson slice
-    fn insert (&mut self, node_id: u64, insert_pos: usize, data_to_insert: &str) -> Result<(), Error> {
-        let mut slice_range = 0;
+    fn set_value (&mut self, node_id: u64, value: Value) -> Result<(), Error>;
 
-        match self.nodes.get(&node_id) {
-            Some(node) => {
-                let mut t = self.clean(&data_to_insert);
-                // Start instances count (for new_slice method) at the provided node instance number
-                // Subtract 1 to take care of the root instance in the new hson slice
-                let mut start_instance = node.instance - 1;
-                // From what instance the existing nodes should be pushed to the right
-                let mut start = node.instance + 1;
-                // From what position should the new slice should be inserted in the existing data
-                let mut start_idx = node.value[0];
-                let parent_id = node.id;
-
-                // If the inserting position in the node's childs is not the first one,
-                // retrieve the new position based on the child values
-                if insert_pos > 0 {
-                    let child_uid = match node.childs.get(insert_pos - 1) {
-                        Some(id) => id,
-                        None => {
-                            let e = Error::new(ErrorKind::InvalidData, format!("Invalid index {}", insert_pos));
-                            return Err(e);
-                        }
-                    };
-                    let child = match self.nodes.get(child_uid) {
-                        Some(c) => {
-                            if !c.childs.is_empty() {
-                                match self.nodes.get(&c.childs[c.childs.len() - 1]) {
-                                    Some(sc) => sc,
-                                    None => c
-                                }
-                            } else {
-                                c
-                            }
-                        },
-                        None => {
-                            let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", child_uid));
-                            return Err(e);
-                        }
-                    };
+    fn rename_key (&mut self, node_id: u64, key: &str) -> Result<(), Error>;
 
-                    start = child.instance + 1;
-                    start_instance = child.instance - 1;
-                    // Add 2 to take care of comma char
-                    start_idx = child.value[1] + 2;
-                }
+    fn move_node (&mut self, node_id: u64, parent_id: u64, position: usize) -> Result<(), Error>;
 
-                if !node.childs.is_empty() {
-                    // Insert a comma if the inserting position is not the last one and there's not already one
-                    // When inserting in the middle of childs add the comma and push the position of all following nodes
-                    if insert_pos < node.childs.len() && t[t.len() - 2] != COMMA {
-                        t.insert(t.len() - 1, ',');
-                    } else if insert_pos >= node.childs.len() {
-                        let last_child_uid = node.childs[node.childs.len() - 1];
-                        let current_uid = node.id;
-                        self.insert_comma(last_child_uid, current_uid);
-                        start_idx += 1;
-                    }
-                }
+    fn copy_node (&mut self, node_id: u64, parent_id: u64, position: usize) -> Result<u64, Error>;
 
-                // Parsing the new slice occurs only here to allow borrowing as there's no more use of the node variable
-                self.id_count += 1;
-                let s: String = t.into_iter().collect();
-                let s = s.as_str();
-                let mut hson = Hson::new_slice(self.id_count, start_instance);
-                hson.parse(s)?;
+    fn array_push (&mut self, array_id: u64, value: Value) -> Result<u64, Error>;
 
-                let root_id = hson.get_root();
-                if let Some(n) = hson.nodes.get(&root_id) {
-                    slice_range = n.value[1] - n.value[0] - 1;
-                }
+    fn array_insert (&mut self, array_id: u64, idx: usize, value: Value) -> Result<u64, Error>;
 
-                let num_keys = hson.nodes.keys().len() as u64;
-                // How much does the existing nodes must be pushed
-                let distance = num_keys - 1;
-                // Data size subtracting the root's curly bracket chars
-                let data_size = hson.data.len() - 2;
-
-                self.id_count += num_keys;
-                self.right_push_instances(start, distance, data_size)?;
-                hson = self.insert_into_data(hson, start_idx);
-                hson = self.insert_into_nodes(parent_id, start_idx, insert_pos,hson);
-                hson = self.insert_into_cache(hson);
-            },
+    fn array_remove (&mut self, array_id: u64, idx: usize) -> Result<(), Error>;
+
+    fn array_set (&mut self, array_id: u64, idx: usize, value: Value) -> Result<(), Error>;
+}
+
+impl Ops for Hson {
+    /// Insert an hson slice, returns the inserted nodes ids
+    fn insert (&mut self, node_id: u64, insert_pos: usize, data_to_insert: &str) -> Result<InsertResult, Error> {
+        let result = self.insert_slice(node_id, insert_pos, data_to_insert)?;
+        self.checked()?;
+
+        self.emit_inserted(node_id, &result.all);
+
+        Ok(result)
+    }
+
+    /// Insert an hson slice after the last child of the provided node, returns the inserted nodes ids
+    fn append_child (&mut self, node_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error> {
+        let position = match self.nodes.get(&node_id) {
+            Some(node) => node.childs.len(),
             None => {
                 let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
                 return Err(e);
             }
         };
 
-        if let Some(node) = self.nodes.get_mut(&node_id) {
-            node.value[1] += slice_range;
-        };
+        self.insert_relative(node_id, position, data_to_insert)
+    }
 
-        if let Some(c) = self.callback {
-            c(Event::Insert, node_id);
+    /// Insert an hson slice before the first child of the provided node, returns the inserted nodes ids
+    fn prepend_child (&mut self, node_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error> {
+        self.insert_relative(node_id, 0, data_to_insert)
+    }
+
+    /// Insert an hson slice before the provided node, returns the inserted nodes ids
+    fn insert_before (&mut self, sibling_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error> {
+        let (parent_id, position) = self.sibling_position(sibling_id)?;
+
+        self.insert_relative(parent_id, position, data_to_insert)
+    }
+
+    /// Insert an hson slice after the provided node, returns the inserted nodes ids
+    fn insert_after (&mut self, sibling_id: u64, data_to_insert: &str) -> Result<Vec<u64>, Error> {
+        let (parent_id, position) = self.sibling_position(sibling_id)?;
+
+        self.insert_relative(parent_id, position + 1, data_to_insert)
+    }
+
+    /// Insert the root entries of a parsed document without parsing them again, returns the inserted nodes ids
+    fn graft (&mut self, parent_id: u64, position: usize, other: Hson) -> Result<InsertResult, Error> {
+        self.check_insert(parent_id, position, || other.root_entries())?;
+        if other.indexes.is_empty() {
+            let e = Error::new(ErrorKind::InvalidData, "Cannot graft an empty document");
+            return Err(e);
         }
 
-        Ok(())
+        let result = self.write_slice(parent_id, position, &other)?;
+        self.absorb_meta(other.transfer_meta(&result.mapping));
+        self.checked()?;
+
+        self.emit_inserted(parent_id, &result.all);
+
+        Ok(result)
     }
 
     /// Remove a node and all its childs
     fn remove (&mut self, node_id: u64) -> Result<(), Error> {
-        match self.nodes.get(&node_id) {
-            Some(node) => {
-                let key = self.get_node_key(node);
-                let childs = self.get_all_childs(node_id)?;
-                let instances_range = childs.len() + 1;
-                let start_instance = node.instance + childs.len() as u64 + 1;
-                let parent_id = node.parent;
-                let mut data_start_pos = if node.key != [0, 0] { node.key[0] - 1 } else { node.value[0] };
-                let mut data_end_pos = node.value[1] + 1;
-                let mut data_size = node.value[1] - data_start_pos;
-
-                if data_start_pos > 0 && self.data[data_start_pos - 1] == COMMA {
-                    data_start_pos -= 1;
-                    data_size += 1;
-                }
+        self.check_remove(node_id)?;
+        let info = self.capture(Event::Remove, node_id);
+        self.erase(node_id)?;
+        self.checked()?;
 
-                if self.data[data_end_pos] == COMMA {
-                    data_end_pos += 1;
-                    data_size += 1;
-                }
+        self.emit_captured(info);
 
-                if !key.is_empty() {
-                    self.remove_from_cache(&key, node_id);
-                }
+        Ok(())
+    }
 
-                for child in childs {
-                    if let Some(n) = self.nodes.get(&child) {
-                        let key = self.get_node_key(n);
+    /// Replace a node with the provided hson, returns the inserted nodes ids
+    fn replace (&mut self, node_id: u64, data_to_insert: &str) -> Result<InsertResult, Error> {
+        let (parent_id, position) = self.sibling_position(node_id)?;
+        let slice = self.prepare_slice(data_to_insert)?;
+        self.check_entries(parent_id, &slice, slice.indexes[0])?;
+        self.check_remove(node_id)?;
 
-                        if !key.is_empty() {
-                            self.remove_from_cache(&key, child);
-                        }
-                    }
-                }
+        self.transaction(|hson| {
+            let mut info = hson.capture(Event::Replace, node_id);
+            hson.erase(node_id)?;
+            hson.check_insert(parent_id, position, || slice.root_entries())?;
 
-                self.left_push_instances(start_instance, instances_range as u64, data_size)?;
-                self.remove_from_data(data_start_pos, data_end_pos);
-                self.remove_from_nodes(parent_id, node_id);
+            let result = hson.write_slice(parent_id, position, &slice)?;
+            hson.checked()?;
 
-                if let Some(node) = self.nodes.get_mut(&parent_id) {
-                    node.value[1] -= data_size;
-                };
-            },
-            None => {
-                let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
-                return Err(e);
+            if let Some(ref mut info) = info {
+                info.inserted = result.all.clone();
+                info.after = slice.indexes.first().and_then(|root| slice.get_value(*root));
             }
-        }
+            hson.emit_captured(info);
 
-        if let Some(c) = self.callback {
-            c(Event::Remove, node_id);
-        }
+            Ok(result)
+        })
+    }
+
+    /// Replace a node value, the node kind follows the value one.
+    /// The node keeps its id, its former childs are dropped
+    fn set_value (&mut self, node_id: u64, value: Value) -> Result<(), Error> {
+        self.check_update(node_id, None, Some(&value))?;
+        let info = self.capture(Event::Update, node_id);
+        self.assign_value(node_id, &value)?;
+        self.checked()?;
+        self.emit_captured(info);
 
         Ok(())
     }
 
-    /// Replace a node with the provided hson
-    fn replace (&mut self, node_id: u64, data_to_insert: &str) -> Result<(), Error> {
-        if let Some(node) = self.get_vertex(node_id) {
-            let parent_id = node.parent;
+    /// Rename a node key, the node keeps its id
+    fn rename_key (&mut self, node_id: u64, key: &str) -> Result<(), Error> {
+        self.check_update(node_id, Some(key), None)?;
+        let info = self.capture(Event::Update, node_id);
+        self.write_key(node_id, key)?;
+        self.checked()?;
+        self.emit_captured(info);
 
-            if let Some(parent) = self.get_vertex(parent_id) {
-                let position = self.get_child_position(node_id, parent_id)?;
+        Ok(())
+    }
 
-                self.remove(node_id)?;
-                return self.insert(parent.id, position, data_to_insert)
-            }
-        }
+    /// Move a node and its childs to the provided position of a new parent, all nodes keep their ids.
+    /// The position is the final one in the new parent childs
+    fn move_node (&mut self, node_id: u64, parent_id: u64, position: usize) -> Result<(), Error> {
+        self.check_remove(node_id)?;
+        self.check_insert(parent_id, position, || self.node_entry(node_id, parent_id))?;
+        let info = self.capture(Event::Move, node_id);
+        self.relocate(node_id, parent_id, position)?;
+        self.checked()?;
+        self.emit_captured(info);
+
+        Ok(())
+    }
+
+    /// Copy a node and its childs to the provided position of a parent, returns the copy id
+    fn copy_node (&mut self, node_id: u64, parent_id: u64, position: usize) -> Result<u64, Error> {
+        let text = self.movable_entry(node_id, parent_id)?;
+        self.check_insert(parent_id, position, || self.node_entry(node_id, parent_id))?;
+        let (start, stop) = self.subtree_range(node_id)?;
+        let copied = self.indexes[start..stop].to_vec();
+        let ids = self.write_entries(parent_id, position, &text, &[], None)?;
+        let tables = self.transfer_meta(&copied.into_iter().zip(ids.iter().cloned()).collect());
+        self.absorb_meta(tables);
+        self.checked()?;
+
+        self.emit_inserted(parent_id, &ids);
+
+        Ok(ids[0])
+    }
+
+    /// Add a value at the end of an array, returns the new element id
+    fn array_push (&mut self, array_id: u64, value: Value) -> Result<u64, Error> {
+        let l = self.array_elements(array_id)?.len();
+
+        self.array_insert(array_id, l, value)
+    }
+
+    /// Insert a value in an array at the provided index, returns the new element id
+    fn array_insert (&mut self, array_id: u64, idx: usize, value: Value) -> Result<u64, Error> {
+        self.array_elements(array_id)?;
+
+        self.check_insert(array_id, idx, || vec![(String::new(), value.clone())])?;
+        let text: Vec<char> = value.to_hson().chars().collect();
+        let ids = self.write_entries(array_id, idx, &text, &[], None)?;
+        self.checked()?;
+
+        self.emit_inserted(array_id, &ids);
+
+        Ok(ids[0])
+    }
+
+    /// Remove the array element at the provided index
+    fn array_remove (&mut self, array_id: u64, idx: usize) -> Result<(), Error> {
+        let element_id = self.array_element(array_id, idx)?;
+
+        self.remove(element_id)
+    }
+
+    /// Replace the array element at the provided index, the element keeps its id
+    fn array_set (&mut self, array_id: u64, idx: usize, value: Value) -> Result<(), Error> {
+        let element_id = self.array_element(array_id, idx)?;
 
-        let e = Error::new(ErrorKind::InvalidData, format!("{} does not exist", node_id));
-        return Err(e);
+        self.set_value(element_id, value)
     }
 }
 
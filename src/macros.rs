@@ -0,0 +1,122 @@
+//! Helpers used by the `hson!` macro expansion
+
+use crate::value::{ escape, Value };
+
+
+/// Build an `Hson` from a literal document.
+///
+/// Keys must be string literals and can be repeated. Values are objects, arrays,
+/// literals, `null` or Rust expressions wrapped in parentheses, which are converted
+/// with `Value::from` and escaped.
+///
+/// ```
+/// use hson::hson;
+///
+/// let text = "Hello \"world\"";
+/// let hson = hson!{
+///     "div": {
+///         "attrs": { "class": ["a", "b"], "tabindex": -1 },
+///         "p": { "text": (text) },
+///         "p": { "text": "x" }
+///     }
+/// };
+///
+/// assert_eq!(hson.stringify(), r#"{"div":{"attrs":{"class":["a","b"],"tabindex":-1},"p":{"text":"Hello \"world\""},"p":{"text":"x"}}}"#);
+/// ```
+#[macro_export]
+macro_rules! hson {
+    ($($body:tt)*) => {{
+        let mut data = String::new();
+        $crate::hson_internal!(@object data $($body)*);
+
+        let mut hson = $crate::Hson::new();
+        hson.parse(&data).expect("hson! produced invalid data");
+        hson
+    }};
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! hson_internal {
+    (@object $s:ident $($body:tt)*) => {
+        $s.push('{');
+        $crate::hson_internal!(@entries $s $($body)*);
+        $s.push('}');
+    };
+
+    (@array $s:ident $($body:tt)*) => {
+        $s.push('[');
+        $crate::hson_internal!(@elements $s $($body)*);
+        $s.push(']');
+    };
+
+    (@entries $s:ident) => {};
+
+    (@entries $s:ident $key:literal : - $v:literal $(, $($rest:tt)*)?) => {
+        $crate::macros::push_key(&mut $s, $key);
+        $crate::hson_internal!(@value $s (-$v));
+        $crate::hson_internal!(@entries $s $($($rest)*)?);
+    };
+
+    (@entries $s:ident $key:literal : $v:tt $(, $($rest:tt)*)?) => {
+        $crate::macros::push_key(&mut $s, $key);
+        $crate::hson_internal!(@value $s $v);
+        $crate::hson_internal!(@entries $s $($($rest)*)?);
+    };
+
+    (@elements $s:ident) => {};
+
+    (@elements $s:ident - $v:literal $(, $($rest:tt)*)?) => {
+        $crate::macros::push_separator(&mut $s);
+        $crate::hson_internal!(@value $s (-$v));
+        $crate::hson_internal!(@elements $s $($($rest)*)?);
+    };
+
+    (@elements $s:ident $v:tt $(, $($rest:tt)*)?) => {
+        $crate::macros::push_separator(&mut $s);
+        $crate::hson_internal!(@value $s $v);
+        $crate::hson_internal!(@elements $s $($($rest)*)?);
+    };
+
+    (@value $s:ident null) => {
+        $s.push_str("null");
+    };
+
+    (@value $s:ident { $($body:tt)* }) => {
+        $crate::hson_internal!(@object $s $($body)*);
+    };
+
+    (@value $s:ident [ $($body:tt)* ]) => {
+        $crate::hson_internal!(@array $s $($body)*);
+    };
+
+    (@value $s:ident ( $e:expr )) => {
+        $crate::macros::push_value(&mut $s, $e);
+    };
+
+    (@value $s:ident $l:literal) => {
+        $crate::macros::push_value(&mut $s, $l);
+    };
+}
+
+
+/// Push a comma if the data does not start a new object or array
+pub fn push_separator (s: &mut String) {
+    match s.chars().last() {
+        Some('{') | Some('[') | None => {},
+        _ => s.push(',')
+    }
+}
+
+/// Push an escaped key followed by colons
+pub fn push_key (s: &mut String, key: &str) {
+    push_separator(s);
+    s.push('"');
+    s.push_str(&escape(key));
+    s.push_str("\":");
+}
+
+/// Push the hson representation of a value
+pub fn push_value<T: Into<Value>> (s: &mut String, value: T) {
+    s.push_str(&value.into().to_hson());
+}
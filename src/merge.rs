@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Kind, Ops, Value, HsonPath, Segment };
+
+
+/// How the root entries of a document are merged into another one
+#[derive(PartialEq, Clone, Debug)]
+pub enum MergeStrategy {
+    /// Append all the entries after the existing ones
+    AppendChildren,
+    /// Merge objects found under the same key recursively, other values replace the existing ones
+    DeepMerge,
+    /// Entries replace the existing ones found under the same key
+    ReplaceOnConflict
+}
+
+/// Edits of the same node that cannot be merged, values are absent where the node is
+#[derive(PartialEq, Clone, Debug)]
+pub struct Conflict {
+    pub path: HsonPath,
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>
+}
+
+
+impl Hson {
+    /// Merge another document into this one. Entries are paired by key and occurrence,
+    /// the second `p` entry of the other document matches the second `p` entry of this one.
+    /// Unpaired entries are appended. The merge is atomic
+    pub fn merge (&mut self, other: &Hson, strategy: MergeStrategy) -> Result<(), Error> {
+        let root = match self.indexes.first() {
+            Some(root) => *root,
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, "Cannot merge into an empty document");
+                return Err(e);
+            }
+        };
+        let other_root = match other.indexes.first() {
+            Some(root) => *root,
+            None => return Ok(())
+        };
+
+        self.transaction(|hson| {
+            match strategy {
+                MergeStrategy::AppendChildren => {
+                    let position = hson.nodes[&root].childs.len();
+                    hson.graft(root, position, other.clone())?;
+                },
+                MergeStrategy::DeepMerge => hson.merge_childs(root, other, other_root, true)?,
+                MergeStrategy::ReplaceOnConflict => hson.merge_childs(root, other, other_root, false)?
+            }
+
+            Ok(())
+        })
+    }
+
+    fn merge_childs (&mut self, parent_id: u64, other: &Hson, other_parent: u64, deep: bool) -> Result<(), Error> {
+        let mut occurrences: HashMap<String, usize> = HashMap::new();
+
+        for uid in &other.nodes[&other_parent].childs {
+            let source = &other.nodes[uid];
+            let key = other.get_node_key(source);
+            let occurrence = occurrences.entry(key.clone()).or_insert(0);
+            let target = self.nodes[&parent_id].childs.iter()
+                .filter(|id| self.get_node_key(&self.nodes[id]) == key)
+                .nth(*occurrence)
+                .cloned();
+            *occurrence += 1;
+
+            let extracted = match other.extract(*uid) {
+                Some(extracted) => extracted,
+                None => {
+                    let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", uid));
+                    return Err(e);
+                }
+            };
+
+            match target {
+                Some(target_id) => {
+                    if deep && source.kind == Kind::Node && self.nodes[&target_id].kind == Kind::Node {
+                        self.merge_childs(target_id, other, *uid, deep)?;
+                    } else {
+                        let (_, position) = self.sibling_position(target_id)?;
+                        self.graft(parent_id, position, extracted)?;
+                        self.remove(target_id)?;
+                    }
+                },
+                None => {
+                    let position = self.nodes[&parent_id].childs.len();
+                    self.graft(parent_id, position, extracted)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Merge the changes made to a common base document by two editors.
+/// Edits of distinct nodes are combined, edits of the same node are reported as conflicts.
+/// Object entries are aligned on the base ones like `diff` does, equal entries on their longest
+/// common subsequence and the others by key in between. Entries keep our order, entries added
+/// by them follow their previous sibling. Arrays are merged element by element when
+/// their length is unchanged
+pub fn merge3 (base: &Hson, ours: &Hson, theirs: &Hson) -> Result<Hson, Vec<Conflict>> {
+    let root_value = |hson: &Hson| hson.indexes.first().and_then(|root| hson.get_value(*root)).unwrap_or(Value::Node(Vec::new()));
+    let mut conflicts = Vec::new();
+
+    let merged = merge_values(&HsonPath::root(), Some(&root_value(base)), Some(&root_value(ours)), Some(&root_value(theirs)), &mut conflicts);
+
+    if !conflicts.is_empty() {
+        return Err(conflicts);
+    }
+
+    let mut hson = Hson::new();
+    if let Some(value) = merged {
+        // Values are taken from parsed documents, their serialization always parses back
+        hson.parse(&value.to_hson()).expect("Merged values always serialize to valid hson");
+    }
+
+    Ok(hson)
+}
+
+fn merge_values (path: &HsonPath, base: Option<&Value>, ours: Option<&Value>, theirs: Option<&Value>, conflicts: &mut Vec<Conflict>) -> Option<Value> {
+    if ours == theirs {
+        return ours.cloned();
+    }
+
+    if ours == base {
+        return theirs.cloned();
+    }
+
+    if theirs == base {
+        return ours.cloned();
+    }
+
+    match (base, ours, theirs) {
+        (base, Some(Value::Node(o)), Some(Value::Node(t))) => {
+            let empty = Vec::new();
+            let b = match base {
+                Some(Value::Node(b)) => b,
+                _ => &empty
+            };
+
+            return Some(Value::Node(merge_entries(path, b, o, t, conflicts)));
+        },
+        (Some(Value::Array(b)), Some(Value::Array(o)), Some(Value::Array(t))) if b.len() == o.len() && o.len() == t.len() => {
+            let values = (0..b.len())
+                .filter_map(|i| merge_values(&path.child(Segment::Index(i)), Some(&b[i]), Some(&o[i]), Some(&t[i]), conflicts))
+                .collect();
+
+            return Some(Value::Array(values));
+        },
+        _ => {}
+    }
+
+    conflicts.push(Conflict {
+        path: path.clone(),
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned()
+    });
+
+    ours.cloned()
+}
+
+/// Entry of a merged object, either the base entry it comes from
+/// or its key and occurrence among the entries added on a side
+#[derive(PartialEq, Clone, Debug)]
+enum Identity {
+    Base(usize),
+    Added(String, usize)
+}
+
+/// Align entries on the base ones. Equal entries are paired on their longest common subsequence,
+/// entries left between two paired ones are paired by key in order, the others are added
+fn identities (base: &[(String, Value)], entries: &[(String, Value)]) -> Vec<Identity> {
+    let n = base.len();
+    let m = entries.len();
+    let mut lengths = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if base[i] == entries[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut ids = vec![None; m];
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && base[i] == entries[j] && lengths[i][j] == lengths[i + 1][j + 1] + 1 {
+            pair_gap(base, entries, &mut removed, &mut added, &mut ids);
+            ids[j] = Some(i);
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lengths[i][j + 1] >= lengths[i + 1][j]) {
+            added.push(j);
+            j += 1;
+        } else {
+            removed.push(i);
+            i += 1;
+        }
+    }
+    pair_gap(base, entries, &mut removed, &mut added, &mut ids);
+
+    let mut occurrences: HashMap<&str, usize> = HashMap::new();
+    ids.into_iter().zip(entries.iter()).map(|(id, (key, _))| match id {
+        Some(i) => Identity::Base(i),
+        None => {
+            let occurrence = occurrences.entry(key).or_insert(0);
+            *occurrence += 1;
+
+            Identity::Added(key.clone(), *occurrence - 1)
+        }
+    }).collect()
+}
+
+/// Pair the entries of a gap between two equal entries by key in order
+fn pair_gap (base: &[(String, Value)], entries: &[(String, Value)], removed: &mut Vec<usize>, added: &mut Vec<usize>, ids: &mut [Option<usize>]) {
+    for j in added.drain(..) {
+        if let Some(p) = removed.iter().position(|i| base[*i].0 == entries[j].0) {
+            ids[j] = Some(removed.remove(p));
+        }
+    }
+
+    removed.clear();
+}
+
+fn merge_entries (path: &HsonPath, base: &[(String, Value)], ours: &[(String, Value)], theirs: &[(String, Value)], conflicts: &mut Vec<Conflict>) -> Vec<(String, Value)> {
+    let base_ids: Vec<Identity> = (0..base.len()).map(Identity::Base).collect();
+    let ours_ids = identities(base, ours);
+    let theirs_ids = identities(base, theirs);
+    let find = |ids: &[Identity], entries: &[(String, Value)], id: &Identity| ids.iter().position(|i| i == id).map(|p| entries[p].clone());
+
+    // Our order first, then their other entries after their previous sibling
+    let mut order = ours_ids.clone();
+    for (i, id) in theirs_ids.iter().enumerate() {
+        if order.contains(id) {
+            continue;
+        }
+
+        let position = theirs_ids[..i].iter().rev()
+            .find_map(|previous| order.iter().position(|o| o == previous))
+            .map(|p| p + 1)
+            .unwrap_or(0);
+        order.insert(position, id.clone());
+    }
+
+    let mut entries: Vec<(String, Value)> = Vec::new();
+    for id in order {
+        let b = find(&base_ids, base, &id);
+        let o = find(&ours_ids, ours, &id);
+        let t = find(&theirs_ids, theirs, &id);
+        let key = match b.as_ref().or(o.as_ref()).or(t.as_ref()) {
+            Some((key, _)) => key.clone(),
+            None => continue
+        };
+
+        // Duplicated keys are located by their occurrence in the merged entries
+        let shared = [base, ours, theirs].iter().any(|entries| entries.iter().filter(|(k, _)| k == &key).count() > 1);
+        let occurrence = entries.iter().filter(|(k, _)| k == &key).count();
+        let segment = Segment::Key(key.clone(), if shared { Some(occurrence) } else { None });
+
+        if let Some(value) = merge_values(&path.child(segment), b.map(|(_, v)| v).as_ref(), o.map(|(_, v)| v).as_ref(), t.map(|(_, v)| v).as_ref(), conflicts) {
+            entries.push((key, value));
+        }
+    }
+
+    entries
+}
@@ -0,0 +1,143 @@
+use std::any::{ Any, TypeId };
+use std::collections::HashMap;
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Node };
+
+
+/// Type erased storage of the metadata of a type
+pub(crate) trait MetaTable: Send + Sync {
+    /// Drop the metadata of the nodes which no longer exist
+    fn retain (&mut self, nodes: &HashMap<u64, Node>);
+
+    /// Copy the metadata of the mapped nodes to their new ids
+    fn transfer (&self, mapping: &HashMap<u64, u64>) -> Box<dyn MetaTable>;
+
+    /// Take the metadata of a table of the same type
+    fn absorb (&mut self, other: Box<dyn MetaTable>);
+
+    fn clone_table (&self) -> Box<dyn MetaTable>;
+
+    fn as_any (&self) -> &dyn Any;
+
+    fn as_any_mut (&mut self) -> &mut dyn Any;
+
+    fn into_any (self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Clone + Send + Sync + 'static> MetaTable for HashMap<u64, T> {
+    fn retain (&mut self, nodes: &HashMap<u64, Node>) {
+        HashMap::retain(self, |id, _| nodes.contains_key(id));
+    }
+
+    fn transfer (&self, mapping: &HashMap<u64, u64>) -> Box<dyn MetaTable> {
+        let values: HashMap<u64, T> = self.iter()
+            .filter_map(|(id, value)| mapping.get(id).map(|new_id| (*new_id, value.clone())))
+            .collect();
+
+        Box::new(values)
+    }
+
+    fn absorb (&mut self, other: Box<dyn MetaTable>) {
+        if let Ok(values) = other.into_any().downcast::<HashMap<u64, T>>() {
+            self.extend(*values);
+        }
+    }
+
+    fn clone_table (&self) -> Box<dyn MetaTable> {
+        Box::new(self.clone())
+    }
+
+    fn as_any (&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut (&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any (self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Clone for Box<dyn MetaTable> {
+    fn clone (&self) -> Self {
+        self.clone_table()
+    }
+}
+
+
+impl Hson {
+    /// Attach a value to a node, returns the value of the same type previously attached.
+    /// Values are dropped along with their node and follow it when it is moved or copied.
+    /// Values are `Send + Sync`, like the document
+    pub fn set_meta<T: Clone + Send + Sync + 'static> (&mut self, node_id: u64, value: T) -> Result<Option<T>, Error> {
+        if !self.nodes.contains_key(&node_id) {
+            let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+            return Err(e);
+        }
+
+        let table = self.meta.entry(TypeId::of::<T>()).or_insert_with(|| Box::new(HashMap::<u64, T>::new()));
+
+        Ok(table.as_any_mut().downcast_mut::<HashMap<u64, T>>().and_then(|values| values.insert(node_id, value)))
+    }
+
+    /// Value of a type attached to a node
+    pub fn get_meta<T: 'static> (&self, node_id: u64) -> Option<&T> {
+        self.meta.get(&TypeId::of::<T>())?
+            .as_any()
+            .downcast_ref::<HashMap<u64, T>>()?
+            .get(&node_id)
+    }
+
+    /// Mutable value of a type attached to a node
+    pub fn get_meta_mut<T: 'static> (&mut self, node_id: u64) -> Option<&mut T> {
+        self.meta.get_mut(&TypeId::of::<T>())?
+            .as_any_mut()
+            .downcast_mut::<HashMap<u64, T>>()?
+            .get_mut(&node_id)
+    }
+
+    /// Detach the value of a type from a node
+    pub fn remove_meta<T: 'static> (&mut self, node_id: u64) -> Option<T> {
+        self.meta.get_mut(&TypeId::of::<T>())?
+            .as_any_mut()
+            .downcast_mut::<HashMap<u64, T>>()?
+            .remove(&node_id)
+    }
+
+    /// Values of a type attached to the nodes, in document order
+    pub fn meta<T: 'static> (&self) -> Vec<(u64, &T)> {
+        let values = match self.meta.get(&TypeId::of::<T>()).and_then(|table| table.as_any().downcast_ref::<HashMap<u64, T>>()) {
+            Some(values) => values,
+            None => return Vec::new()
+        };
+
+        self.indexes.iter().filter_map(|id| values.get(id).map(|value| (*id, value))).collect()
+    }
+
+    /// Drop the metadata of the removed nodes
+    pub(crate) fn prune_meta (&mut self) {
+        for table in self.meta.values_mut() {
+            table.retain(&self.nodes);
+        }
+    }
+
+    /// Metadata of the mapped nodes under their new ids
+    pub(crate) fn transfer_meta (&self, mapping: &HashMap<u64, u64>) -> Vec<(TypeId, Box<dyn MetaTable>)> {
+        self.meta.iter().map(|(type_id, table)| (*type_id, table.transfer(mapping))).collect()
+    }
+
+    /// Add metadata taken from `transfer_meta`
+    pub(crate) fn absorb_meta (&mut self, tables: Vec<(TypeId, Box<dyn MetaTable>)>) {
+        for (type_id, values) in tables {
+            match self.meta.get_mut(&type_id) {
+                Some(table) => table.absorb(values),
+                None => {
+                    self.meta.insert(type_id, values);
+                }
+            }
+        }
+    }
+}
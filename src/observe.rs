@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::io::{ ErrorKind, Error };
+use std::sync::Mutex;
+
+use crate::{ Hson, Node, Event, EventInfo, SubscriptionId };
+
+
+/// Changes reported to an observer
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct ObserveOptions {
+    /// Also report the changes made to the descendants of the observed node
+    pub subtree: bool,
+    /// Report insertions, removals, moves and replacements of childs
+    pub child_list: bool,
+    /// Report value updates and renames
+    pub values: bool
+}
+
+/// Kind of change reported by a mutation record
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum MutationKind {
+    ChildList,
+    Value
+}
+
+/// Change of an observed node
+#[derive(PartialEq, Clone, Debug)]
+pub struct MutationRecord {
+    pub kind: MutationKind,
+    /// Node whose childs or value changed
+    pub target: u64,
+    pub info: EventInfo
+}
+
+/// Mutation observer, called with the records of an operation or of a whole transaction
+pub type Observer = Box<dyn FnMut(&[MutationRecord]) + Send>;
+
+/// Registered observers
+#[derive(Default)]
+pub(crate) struct Observers {
+    entries: Vec<(SubscriptionId, u64, ObserveOptions, Mutex<Observer>)>
+}
+
+impl Observers {
+    pub(crate) fn is_empty (&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove an observer, returns false if it was not registered
+    pub(crate) fn remove (&mut self, id: SubscriptionId) -> bool {
+        let l = self.entries.len();
+        self.entries.retain(|(uid, ..)| *uid != id);
+
+        self.entries.len() != l
+    }
+
+    /// Drop the observers of the nodes which no longer exist
+    fn retain (&mut self, nodes: &HashMap<u64, Node>) {
+        self.entries.retain(|(_, node_id, ..)| nodes.contains_key(node_id));
+    }
+
+    /// Records of an event for the observers it concerns, matched against the document
+    /// as it is right after the change
+    pub(crate) fn records (&self, hson: &Hson, info: &EventInfo) -> Vec<(SubscriptionId, MutationRecord)> {
+        let (kind, targets) = match info.event {
+            Event::Insert |
+            Event::Remove |
+            Event::Replace => (MutationKind::ChildList, vec![info.parent]),
+            Event::Move => (MutationKind::ChildList, vec![info.parent, info.from.map(|(parent, _)| parent).unwrap_or(info.parent)]),
+            Event::Update => (MutationKind::Value, vec![info.node]),
+            Event::Parse => return Vec::new()
+        };
+
+        let mut records = Vec::new();
+        for (id, node_id, options, _) in &self.entries {
+            let wanted = match kind {
+                MutationKind::ChildList => options.child_list,
+                MutationKind::Value => options.values
+            };
+            if !wanted {
+                continue;
+            }
+
+            let target = targets.iter().find(|target| *target == node_id || (options.subtree && hson.is_descendant(*node_id, **target)));
+            if let Some(target) = target {
+                records.push((*id, MutationRecord { kind, target: *target, info: info.clone() }));
+            }
+        }
+
+        records
+    }
+
+    /// Call each observer once with its records
+    pub(crate) fn deliver (&self, records: Vec<(SubscriptionId, MutationRecord)>) {
+        if records.is_empty() {
+            return;
+        }
+
+        for (id, .., observer) in &self.entries {
+            let batch: Vec<MutationRecord> = records.iter().filter(|(uid, _)| uid == id).map(|(_, record)| record.clone()).collect();
+
+            if batch.is_empty() {
+                continue;
+            }
+
+            if let Ok(mut observer) = observer.lock() {
+                observer(&batch);
+            }
+        }
+    }
+}
+
+
+impl Hson {
+    /// Observe the changes of a node, and of its descendants with the `subtree` option.
+    /// Records are delivered once per operation, or once the outermost transaction succeeds.
+    /// The observer is dropped along with the node
+    pub fn observe (&mut self, node_id: u64, options: ObserveOptions, observer: Observer) -> Result<SubscriptionId, Error> {
+        if !self.nodes.contains_key(&node_id) {
+            let e = Error::new(ErrorKind::InvalidData, format!("Invalid uid {}", node_id));
+            return Err(e);
+        }
+
+        let id = self.next_subscription();
+        self.observers.entries.push((id, node_id, options, Mutex::new(observer)));
+
+        Ok(id)
+    }
+
+    /// Drop the observers of the removed nodes. A transaction may restore the nodes,
+    /// observers are only dropped once the outermost one succeeds
+    pub(crate) fn prune_observers (&mut self) {
+        if self.pending.is_none() {
+            self.observers.retain(&self.nodes);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Ops, Value, Change, HsonPath, Segment, escape };
+
+
+/// Patch operation, paths are resolved once the previous operations are applied
+#[derive(PartialEq, Clone, Debug)]
+pub enum PatchOp {
+    /// Add a node at the provided position of the parent located by the path.
+    /// The path last segment holds the key, or the index of an array element
+    Add { path: HsonPath, position: usize, value: Value },
+    Remove { path: HsonPath },
+    /// Replace a node value, the node keeps its id
+    Replace { path: HsonPath, value: Value },
+    Rename { path: HsonPath, key: String },
+    /// Move a node at the provided position of the parent located by the path
+    Move { from: HsonPath, path: HsonPath, position: usize }
+}
+
+/// Ordered list of operations addressing nodes by pointer paths.
+/// Serialized as an hson document with an entry per operation:
+/// `{"remove":{"path":"/html/body/div[1]"},"add":{"path":"/html/body/p","position":0,"value":"Hi"}}`
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Patch {
+    pub ops: Vec<PatchOp>
+}
+
+impl Patch {
+    /// Patch applying the changes listed by `diff`
+    pub fn from_changes (changes: &[Change]) -> Patch {
+        let ops = changes.iter().map(|change| {
+            match change {
+                Change::Added { path, position, value } => PatchOp::Add { path: path.clone(), position: *position, value: value.clone() },
+                Change::Removed { path, .. } => PatchOp::Remove { path: path.clone() },
+                Change::Moved { from, to, position } => PatchOp::Move { from: from.clone(), path: to.clone(), position: *position },
+                Change::ValueChanged { path, after, .. } => PatchOp::Replace { path: path.clone(), value: after.clone() },
+                Change::KeyRenamed { path, after, .. } => PatchOp::Rename { path: path.clone(), key: after.clone() }
+            }
+        }).collect();
+
+        Patch { ops }
+    }
+
+    /// Hson representation of the patch
+    pub fn to_hson (&self) -> String {
+        let entries = self.ops.iter().map(|op| {
+            let pointer = |path: &HsonPath| Value::String(path.to_pointer());
+            let (name, fields) = match op {
+                PatchOp::Add { path, position, value } => ("add", vec![("path", pointer(path)), ("position", Value::from(*position)), ("value", value.clone())]),
+                PatchOp::Remove { path } => ("remove", vec![("path", pointer(path))]),
+                PatchOp::Replace { path, value } => ("replace", vec![("path", pointer(path)), ("value", value.clone())]),
+                PatchOp::Rename { path, key } => ("rename", vec![("path", pointer(path)), ("key", Value::from(key))]),
+                PatchOp::Move { from, path, position } => ("move", vec![("from", pointer(from)), ("path", pointer(path)), ("position", Value::from(*position))])
+            };
+
+            (name.to_string(), Value::Node(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect()))
+        }).collect();
+
+        Value::Node(entries).to_hson()
+    }
+
+    /// Parse the hson representation of a patch
+    pub fn parse (s: &str) -> Result<Patch, Error> {
+        let mut hson = Hson::new();
+        hson.parse(s)?;
+
+        let entries = match hson.indexes.first().and_then(|root| hson.get_value(*root)) {
+            Some(Value::Node(entries)) => entries,
+            _ => {
+                let e = Error::new(ErrorKind::InvalidData, "A patch must be an hson object");
+                return Err(e);
+            }
+        };
+
+        let mut ops = Vec::new();
+        for (name, value) in entries {
+            let fields = match value {
+                Value::Node(fields) => fields,
+                _ => {
+                    let e = Error::new(ErrorKind::InvalidData, format!("Invalid {} operation", name));
+                    return Err(e);
+                }
+            };
+            let field = |key: &str| -> Result<Value, Error> {
+                match fields.iter().find(|(k, _)| k == key) {
+                    Some((_, v)) => Ok(v.clone()),
+                    None => {
+                        let e = Error::new(ErrorKind::InvalidData, format!("Missing {} in {} operation", key, name));
+                        Err(e)
+                    }
+                }
+            };
+            let pointer = |key: &str| -> Result<HsonPath, Error> {
+                match field(key)? {
+                    Value::String(s) => HsonPath::from_pointer(&s),
+                    _ => {
+                        let e = Error::new(ErrorKind::InvalidData, format!("Invalid {} in {} operation", key, name));
+                        Err(e)
+                    }
+                }
+            };
+            let position = || -> Result<usize, Error> {
+                match field("position")? {
+                    Value::Integer(n) if n >= 0 => Ok(n as usize),
+                    _ => {
+                        let e = Error::new(ErrorKind::InvalidData, format!("Invalid position in {} operation", name));
+                        Err(e)
+                    }
+                }
+            };
+
+            let op = match name.as_str() {
+                "add" => PatchOp::Add { path: pointer("path")?, position: position()?, value: field("value")? },
+                "remove" => PatchOp::Remove { path: pointer("path")? },
+                "replace" => PatchOp::Replace { path: pointer("path")?, value: field("value")? },
+                "rename" => {
+                    match field("key")? {
+                        Value::String(key) => PatchOp::Rename { path: pointer("path")?, key },
+                        _ => {
+                            let e = Error::new(ErrorKind::InvalidData, "Invalid key in rename operation");
+                            return Err(e);
+                        }
+                    }
+                },
+                "move" => PatchOp::Move { from: pointer("from")?, path: pointer("path")?, position: position()? },
+                _ => {
+                    let e = Error::new(ErrorKind::InvalidData, format!("Unknown patch operation {}", name));
+                    return Err(e);
+                }
+            };
+
+            ops.push(op);
+        }
+
+        Ok(Patch { ops })
+    }
+}
+
+
+impl Hson {
+    /// Apply the operations of a patch in order. The patch is atomic
+    pub fn apply_patch (&mut self, patch: &Patch) -> Result<(), Error> {
+        self.transaction(|hson| {
+            for op in &patch.ops {
+                hson.apply_op(op)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn apply_op (&mut self, op: &PatchOp) -> Result<(), Error> {
+        match op {
+            PatchOp::Add { path, position, value } => {
+                let parent_id = self.resolve_parent(path)?;
+
+                match path.segments.last() {
+                    Some(Segment::Key(key, _)) => {
+                        let entry = format!("{{\"{}\":{}}}", escape(key), value.to_hson());
+                        self.insert(parent_id, *position, &entry)?;
+                    },
+                    _ => {
+                        self.array_insert(parent_id, *position, value.clone())?;
+                    }
+                }
+            },
+            PatchOp::Remove { path } => {
+                let node_id = self.resolve_or_fail(path)?;
+                self.remove(node_id)?;
+            },
+            PatchOp::Replace { path, value } => {
+                let node_id = self.resolve_or_fail(path)?;
+                self.set_value(node_id, value.clone())?;
+            },
+            PatchOp::Rename { path, key } => {
+                let node_id = self.resolve_or_fail(path)?;
+                self.rename_key(node_id, key)?;
+            },
+            PatchOp::Move { from, path, position } => {
+                let node_id = self.resolve_or_fail(from)?;
+                let parent_id = self.resolve_parent(path)?;
+                self.move_node(node_id, parent_id, *position)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_or_fail (&self, path: &HsonPath) -> Result<u64, Error> {
+        match self.resolve(path) {
+            Some(node_id) => Ok(node_id),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, format!("No node at {}", path.to_pointer()));
+                Err(e)
+            }
+        }
+    }
+
+    fn resolve_parent (&self, path: &HsonPath) -> Result<u64, Error> {
+        match path.parent() {
+            Some(parent) => self.resolve_or_fail(&parent),
+            None => {
+                let e = Error::new(ErrorKind::InvalidData, "The root has no parent");
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::str::FromStr;
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Kind, unescape, BACKSLASH, DOUBLE_QUOTES, OPEN_ARR, CLOSE_ARR };
+
+
+/// Step from a node to one of its childs
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum Segment {
+    /// Unescaped key, with the index among the siblings sharing the key when there are several
+    Key(String, Option<usize>),
+    /// Array element index
+    Index(usize)
+}
+
+/// Location of a node from the root, independent of the nodes ids
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct HsonPath {
+    pub segments: Vec<Segment>
+}
+
+impl HsonPath {
+    /// Path of the root node
+    pub fn root () -> HsonPath {
+        HsonPath { segments: Vec::new() }
+    }
+
+    /// Path of a child
+    pub fn child (&self, segment: Segment) -> HsonPath {
+        let mut segments = self.segments.clone();
+        segments.push(segment);
+
+        HsonPath { segments }
+    }
+
+    /// Path of the parent, None for the root
+    pub fn parent (&self) -> Option<HsonPath> {
+        if self.segments.is_empty() {
+            return None;
+        }
+
+        Some(HsonPath { segments: self.segments[..self.segments.len() - 1].to_vec() })
+    }
+
+    pub fn is_root (&self) -> bool {
+        self.segments.is_empty()
+    }
+}
+
+/// Segments separated by `>`, like `html>body>div[0]>class>[1]`.
+/// Backslashes, double quotes, `>`, `[` and `]` are escaped in keys, an empty key is `""`
+impl fmt::Display for HsonPath {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render('>'))
+    }
+}
+
+/// Parse a path rendered with `>` separators
+impl FromStr for HsonPath {
+    type Err = Error;
+
+    fn from_str (s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(HsonPath::root());
+        }
+
+        HsonPath::parse_segments(s, '>')
+    }
+}
+
+impl HsonPath {
+    /// Pointer like `/html/body/div[1]/p[0]`, the root pointer is empty.
+    /// Backslashes, double quotes, `/`, `[` and `]` are escaped in keys, an empty key is `""`
+    pub fn to_pointer (&self) -> String {
+        if self.segments.is_empty() {
+            return String::new();
+        }
+
+        format!("/{}", self.render('/'))
+    }
+
+    /// Parse a pointer produced by `to_pointer`, a key without index is its first occurrence.
+    /// A trailing or repeated `/` is an error, empty keys are written `""`
+    pub fn from_pointer (pointer: &str) -> Result<HsonPath, Error> {
+        if pointer.is_empty() {
+            return Ok(HsonPath::root());
+        }
+
+        if !pointer.starts_with('/') {
+            let e = Error::new(ErrorKind::InvalidData, format!("Invalid pointer {}", pointer));
+            return Err(e);
+        }
+
+        HsonPath::parse_segments(&pointer[1..], '/')
+    }
+
+    fn render (&self, separator: char) -> String {
+        let mut s = String::new();
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                s.push(separator);
+            }
+
+            match segment {
+                Segment::Key(key, index) => {
+                    // Quotes tell an empty key from an array index
+                    if key.is_empty() {
+                        s.push(DOUBLE_QUOTES);
+                        s.push(DOUBLE_QUOTES);
+                    }
+
+                    for c in key.chars() {
+                        if c == BACKSLASH || c == DOUBLE_QUOTES || c == separator || c == OPEN_ARR || c == CLOSE_ARR {
+                            s.push(BACKSLASH);
+                        }
+
+                        s.push(c);
+                    }
+
+                    if let Some(index) = index {
+                        s.push_str(&format!("[{}]", index));
+                    }
+                },
+                Segment::Index(index) => s.push_str(&format!("[{}]", index))
+            }
+        }
+
+        s
+    }
+
+    fn parse_segments (s: &str, separator: char) -> Result<HsonPath, Error> {
+        let mut segments = Vec::new();
+        let mut key = String::new();
+        // Unescaped text following the key, holding the index
+        let mut index: Option<String> = None;
+        // Unescaped double quotes mark a key, even an empty one
+        let mut quoted = false;
+        let mut chars = s.chars();
+
+        loop {
+            let c = chars.next();
+
+            match c {
+                None => {},
+                Some(c) if c == separator => {},
+                Some(BACKSLASH) if index.is_none() => {
+                    match chars.next() {
+                        Some(escaped) => key.push(escaped),
+                        None => {
+                            let e = Error::new(ErrorKind::InvalidData, format!("Invalid path {}", s));
+                            return Err(e);
+                        }
+                    }
+                    continue;
+                },
+                Some(DOUBLE_QUOTES) if index.is_none() => {
+                    quoted = true;
+                    continue;
+                },
+                Some(OPEN_ARR) if index.is_none() => {
+                    index = Some(String::new());
+                    continue;
+                },
+                Some(c) => {
+                    match index {
+                        Some(ref mut index) => index.push(c),
+                        None => key.push(c)
+                    }
+                    continue;
+                }
+            }
+
+            let segment = match index.take() {
+                Some(index) => {
+                    let n = match index.strip_suffix(CLOSE_ARR).and_then(|n| n.parse::<usize>().ok()) {
+                        Some(n) => n,
+                        None => {
+                            let e = Error::new(ErrorKind::InvalidData, format!("Invalid index in path {}", s));
+                            return Err(e);
+                        }
+                    };
+
+                    if key.is_empty() && !quoted { Segment::Index(n) } else { Segment::Key(key.clone(), Some(n)) }
+                },
+                // Empty keys are quoted, an empty segment is a stray separator
+                None if key.is_empty() && !quoted => {
+                    let e = Error::new(ErrorKind::InvalidData, format!("Empty segment in path {}", s));
+                    return Err(e);
+                },
+                None => Segment::Key(key.clone(), None)
+            };
+            segments.push(segment);
+            key.clear();
+            quoted = false;
+
+            if c.is_none() {
+                break;
+            }
+        }
+
+        Ok(HsonPath { segments })
+    }
+}
+
+
+impl Hson {
+    /// Segment leading from the parent of a node to the node
+    pub(crate) fn segment_of (&self, node_id: u64) -> Option<Segment> {
+        let node = self.nodes.get(&node_id)?;
+        let parent = self.nodes.get(&node.parent)?;
+
+        if node.key == [0, 0] {
+            let index = parent.childs.iter().position(|id| id == &node_id)?;
+            return Some(Segment::Index(index));
+        }
+
+        let key = self.get_node_key(node);
+        let mut index = 0;
+        let mut count = 0;
+        for uid in &parent.childs {
+            if self.get_node_key(&self.nodes[uid]) == key {
+                if uid == &node_id {
+                    index = count;
+                }
+
+                count += 1;
+            }
+        }
+
+        Some(Segment::Key(unescape(&key), if count > 1 { Some(index) } else { None }))
+    }
+
+    /// Path of a node from the root, with the index of the keys shared by several siblings.
+    /// Unlike ids, paths stay valid once the document is stringified and parsed again
+    pub fn path_of (&self, node_id: u64) -> Option<HsonPath> {
+        let mut segments = Vec::new();
+        let mut current = node_id;
+
+        loop {
+            let node = self.nodes.get(&current)?;
+            if node.root {
+                break;
+            }
+
+            segments.push(self.segment_of(current)?);
+            current = node.parent;
+        }
+
+        segments.reverse();
+
+        Some(HsonPath { segments })
+    }
+
+    /// Node located by a path, a key without index is its first occurrence
+    pub fn resolve (&self, path: &HsonPath) -> Option<u64> {
+        let mut current = *self.indexes.first()?;
+
+        for segment in &path.segments {
+            let node = self.nodes.get(&current)?;
+
+            current = match segment {
+                Segment::Index(index) => {
+                    if node.kind != Kind::Array {
+                        return None;
+                    }
+
+                    *node.childs.get(*index)?
+                },
+                Segment::Key(key, index) => {
+                    if node.kind != Kind::Node {
+                        return None;
+                    }
+
+                    *node.childs.iter()
+                        .filter(|uid| unescape(&self.get_node_key(&self.nodes[uid])) == *key)
+                        .nth(index.unwrap_or(0))?
+                }
+            };
+        }
+
+        Some(current)
+    }
+}
@@ -0,0 +1,435 @@
+use std::collections::{ HashMap, HashSet };
+use std::io::{ ErrorKind, Error };
+
+use crate::{ Hson, Ops, Value, escape };
+
+
+/// Unique operation identifier ordered by Lamport timestamp, then by replica
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct OpId {
+    pub counter: u64,
+    pub replica: u64
+}
+
+/// Operation exchanged between replicas. Containers are inserted empty,
+/// their content is made of the following operations
+#[derive(PartialEq, Clone, Debug)]
+pub enum ReplicaOp {
+    /// Insert an element in a parent after a sibling, or first when there is none.
+    /// Object entries have a key, array elements do not. The version is the id of the operation
+    /// which gave the parent its value, the element belongs to that value
+    Insert { id: OpId, parent: OpId, after: Option<OpId>, key: Option<String>, value: Value, version: OpId },
+    Remove { id: OpId, target: OpId },
+    /// Replace an element value, the greatest operation id wins.
+    /// The childs of the losing values are hidden, the winning value keeps its own childs only
+    Set { id: OpId, target: OpId, value: Value }
+}
+
+impl ReplicaOp {
+    pub fn id (&self) -> OpId {
+        match self {
+            ReplicaOp::Insert { id, .. } |
+            ReplicaOp::Remove { id, .. } |
+            ReplicaOp::Set { id, .. } => *id
+        }
+    }
+}
+
+/// Replicated element, removed ones are kept as tombstones to order concurrent insertions
+struct Element {
+    parent: OpId,
+    key: Option<String>,
+    /// Operation which gave the parent the value the element belongs to
+    version: OpId,
+    /// Last writer wins register, containers hold an empty value
+    value: (OpId, Value),
+    /// Childs in replicated order, tombstones included
+    childs: Vec<OpId>,
+    removed: bool
+}
+
+/// Replicated document converging whatever the order operations are received in.
+/// Childs are ordered as a replicated growable array, removed elements are kept as
+/// tombstones and concurrent values are resolved by the greatest operation id, along with their childs.
+/// Operations received before the ones they depend on are buffered
+pub struct Replica {
+    id: u64,
+    clock: u64,
+    document: Hson,
+    elements: HashMap<OpId, Element>,
+    /// Document node of each visible element
+    nodes: HashMap<OpId, u64>,
+    seen: HashSet<OpId>,
+    buffer: Vec<ReplicaOp>,
+    outbox: Vec<ReplicaOp>
+}
+
+const ROOT: OpId = OpId { counter: 0, replica: 0 };
+
+impl Replica {
+    /// Create an empty replica, replica ids must be unique and greater than 0
+    pub fn new (id: u64) -> Replica {
+        let mut document = Hson::new();
+        document.parse("{}").expect("Empty document");
+
+        let mut elements = HashMap::new();
+        elements.insert(ROOT, Element {
+            parent: ROOT,
+            key: None,
+            version: ROOT,
+            value: (ROOT, Value::Node(Vec::new())),
+            childs: Vec::new(),
+            removed: false
+        });
+
+        let mut nodes = HashMap::new();
+        nodes.insert(ROOT, document.indexes[0]);
+
+        Replica {
+            id,
+            clock: 0,
+            document,
+            elements,
+            nodes,
+            seen: HashSet::new(),
+            buffer: Vec::new(),
+            outbox: Vec::new()
+        }
+    }
+
+    pub fn id (&self) -> u64 {
+        self.id
+    }
+
+    /// Current state of the document
+    pub fn document (&self) -> &Hson {
+        &self.document
+    }
+
+    /// Root element id
+    pub fn root (&self) -> OpId {
+        ROOT
+    }
+
+    /// Element displayed by a document node
+    pub fn element_of (&self, node_id: u64) -> Option<OpId> {
+        self.nodes.iter().find(|(_, n)| **n == node_id).map(|(id, _)| *id)
+    }
+
+    /// Document node displaying an element
+    pub fn node_of (&self, element: OpId) -> Option<u64> {
+        self.nodes.get(&element).cloned()
+    }
+
+    /// Number of received operations waiting for the ones they depend on
+    pub fn buffered (&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Operations made locally since the last call, to send to the other replicas
+    pub fn take_ops (&mut self) -> Vec<ReplicaOp> {
+        self.outbox.drain(..).collect()
+    }
+
+    /// Insert a value at the provided position of a visible container childs.
+    /// The key is required in objects and forbidden in arrays. Returns the element id
+    pub fn insert (&mut self, parent: OpId, position: usize, key: Option<&str>, value: Value) -> Result<OpId, Error> {
+        let childs = self.visible_childs(parent)?;
+
+        if position > childs.len() {
+            let e = Error::new(ErrorKind::InvalidData, format!("Invalid index {}", position));
+            return Err(e);
+        }
+
+        let is_object = matches!(self.elements[&parent].value.1, Value::Node(_));
+        if is_object != key.is_some() {
+            let e = Error::new(ErrorKind::InvalidData, "Object entries need a key, array elements cannot have one");
+            return Err(e);
+        }
+
+        let after = if position == 0 { None } else { Some(childs[position - 1]) };
+
+        self.insert_after(parent, after, key.map(|k| k.to_string()), value)
+    }
+
+    /// Remove an element and its childs
+    pub fn remove (&mut self, target: OpId) -> Result<(), Error> {
+        if target == ROOT || !self.elements.contains_key(&target) {
+            let e = Error::new(ErrorKind::InvalidData, format!("Invalid element {:?}", target));
+            return Err(e);
+        }
+
+        let id = self.tick();
+        self.local(ReplicaOp::Remove { id, target })
+    }
+
+    /// Replace an element value, its former childs are hidden along with the former value
+    pub fn set_value (&mut self, target: OpId, value: Value) -> Result<(), Error> {
+        if target == ROOT || !self.elements.contains_key(&target) {
+            let e = Error::new(ErrorKind::InvalidData, format!("Invalid element {:?}", target));
+            return Err(e);
+        }
+
+        let id = self.tick();
+        self.local(ReplicaOp::Set { id, target, value: container(&value) })?;
+        self.insert_content(target, &value)
+    }
+
+    /// Apply an operation received from another replica, in any order.
+    /// Operations already received are ignored, an operation which fails stays buffered
+    pub fn apply (&mut self, op: ReplicaOp) -> Result<(), Error> {
+        self.clock = self.clock.max(op.id().counter);
+        self.buffer.push(op);
+
+        // Apply every buffered operation whose dependencies are known
+        loop {
+            let ready = self.buffer.iter().position(|op| self.is_ready(op));
+
+            match ready {
+                Some(idx) => {
+                    self.integrate(self.buffer[idx].clone())?;
+                    self.buffer.remove(idx);
+                },
+                None => break
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tick (&mut self) -> OpId {
+        self.clock += 1;
+
+        OpId { counter: self.clock, replica: self.id }
+    }
+
+    fn local (&mut self, op: ReplicaOp) -> Result<(), Error> {
+        self.outbox.push(op.clone());
+        self.integrate(op)
+    }
+
+    fn insert_after (&mut self, parent: OpId, after: Option<OpId>, key: Option<String>, value: Value) -> Result<OpId, Error> {
+        let id = self.tick();
+        let version = self.elements[&parent].value.0;
+
+        self.local(ReplicaOp::Insert { id, parent, after, key, value: container(&value), version })?;
+        self.insert_content(id, &value)?;
+
+        Ok(id)
+    }
+
+    /// Insert the childs of a container value
+    fn insert_content (&mut self, parent: OpId, value: &Value) -> Result<(), Error> {
+        let mut after = None;
+
+        match value {
+            Value::Node(entries) => {
+                for (key, value) in entries {
+                    after = Some(self.insert_after(parent, after, Some(key.clone()), value.clone())?);
+                }
+            },
+            Value::Array(values) => {
+                for value in values {
+                    after = Some(self.insert_after(parent, after, None, value.clone())?);
+                }
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn is_ready (&self, op: &ReplicaOp) -> bool {
+        match op {
+            ReplicaOp::Insert { parent, after, .. } => {
+                self.elements.contains_key(parent) && after.map(|a| self.elements.contains_key(&a)).unwrap_or(true)
+            },
+            ReplicaOp::Remove { target, .. } |
+            ReplicaOp::Set { target, .. } => self.elements.contains_key(target)
+        }
+    }
+
+    /// Integrate an operation, the replica is left unchanged if the document rejects it
+    fn integrate (&mut self, op: ReplicaOp) -> Result<(), Error> {
+        let id = op.id();
+        if self.seen.contains(&id) {
+            return Ok(());
+        }
+
+        match op {
+            ReplicaOp::Insert { id, parent, after, key, value, version } => {
+                // Concurrent insertions after the same sibling are ordered by decreasing ids
+                let childs = &self.elements[&parent].childs;
+                let mut idx = match after {
+                    Some(after) => childs.iter().position(|c| *c == after).map(|p| p + 1).unwrap_or(0),
+                    None => 0
+                };
+                while idx < childs.len() && childs[idx] > id {
+                    idx += 1;
+                }
+
+                if let Some(element) = self.elements.get_mut(&parent) {
+                    element.childs.insert(idx, id);
+                }
+                self.elements.insert(id, Element {
+                    parent,
+                    key,
+                    version,
+                    value: (id, value),
+                    childs: Vec::new(),
+                    removed: false
+                });
+
+                if let Err(e) = self.show(id) {
+                    self.elements.remove(&id);
+                    if let Some(element) = self.elements.get_mut(&parent) {
+                        element.childs.remove(idx);
+                    }
+
+                    return Err(e);
+                }
+            },
+            ReplicaOp::Remove { target, .. } => {
+                if let Some(node_id) = self.nodes.get(&target).cloned() {
+                    self.document.remove(node_id)?;
+                    self.hide(target);
+                }
+
+                if let Some(element) = self.elements.get_mut(&target) {
+                    element.removed = true;
+                }
+            },
+            ReplicaOp::Set { id, target, value } => {
+                if self.elements[&target].value.0 > id {
+                    self.seen.insert(id);
+                    return Ok(());
+                }
+
+                let former = match self.elements.get_mut(&target) {
+                    Some(element) => std::mem::replace(&mut element.value, (id, value)),
+                    None => return Ok(())
+                };
+
+                if let Some(node_id) = self.nodes.get(&target).cloned() {
+                    if let Err(e) = self.document.set_value(node_id, self.render(target)) {
+                        if let Some(element) = self.elements.get_mut(&target) {
+                            element.value = former;
+                        }
+
+                        return Err(e);
+                    }
+
+                    for child in self.elements[&target].childs.clone() {
+                        self.hide(child);
+                    }
+                    self.map_childs(target, node_id);
+                }
+            }
+        }
+
+        self.seen.insert(id);
+
+        Ok(())
+    }
+
+    /// Is an element displayed in the document
+    fn is_visible (&self, id: OpId) -> bool {
+        if id == ROOT {
+            return true;
+        }
+
+        let element = &self.elements[&id];
+        if element.removed || element.version != self.elements[&element.parent].value.0 {
+            return false;
+        }
+
+        let fits = match self.elements[&element.parent].value.1 {
+            Value::Node(_) => element.key.is_some(),
+            Value::Array(_) => element.key.is_none(),
+            _ => false
+        };
+
+        fits && self.is_visible(element.parent)
+    }
+
+    fn visible_childs (&self, parent: OpId) -> Result<Vec<OpId>, Error> {
+        match self.elements.get(&parent) {
+            Some(element) if self.is_visible(parent) => {
+                Ok(element.childs.iter().filter(|c| self.is_visible(**c)).cloned().collect())
+            },
+            _ => {
+                let e = Error::new(ErrorKind::InvalidData, format!("Invalid element {:?}", parent));
+                Err(e)
+            }
+        }
+    }
+
+    /// Value of an element with its visible childs
+    fn render (&self, id: OpId) -> Value {
+        let element = &self.elements[&id];
+        let childs = element.childs.iter().filter(|c| self.is_visible(**c));
+
+        match element.value.1 {
+            Value::Node(_) => Value::Node(childs.map(|c| (self.elements[c].key.clone().unwrap_or_default(), self.render(*c))).collect()),
+            Value::Array(_) => Value::Array(childs.map(|c| self.render(*c)).collect()),
+            ref value => value.clone()
+        }
+    }
+
+    /// Display a newly inserted element if its parent is displayed
+    fn show (&mut self, id: OpId) -> Result<(), Error> {
+        if !self.is_visible(id) {
+            return Ok(());
+        }
+
+        let element = &self.elements[&id];
+        let parent_node = self.nodes[&element.parent];
+        let position = self.elements[&element.parent].childs.iter()
+            .take_while(|c| **c != id)
+            .filter(|c| self.is_visible(**c))
+            .count();
+        let value = self.render(id);
+
+        let node_id = match element.key {
+            Some(ref key) => {
+                let entry = format!("{{\"{}\":{}}}", escape(key), value.to_hson());
+                self.document.insert(parent_node, position, &entry)?.roots[0]
+            },
+            None => self.document.array_insert(parent_node, position, value)?
+        };
+
+        self.nodes.insert(id, node_id);
+        self.map_childs(id, node_id);
+
+        Ok(())
+    }
+
+    /// Forget the nodes of an element and its childs
+    fn hide (&mut self, id: OpId) {
+        if self.nodes.remove(&id).is_some() {
+            for child in self.elements[&id].childs.clone() {
+                self.hide(child);
+            }
+        }
+    }
+
+    /// Pair the visible childs of an element with the childs of its node
+    fn map_childs (&mut self, id: OpId, node_id: u64) {
+        let childs: Vec<OpId> = self.elements[&id].childs.iter().filter(|c| self.is_visible(**c)).cloned().collect();
+        let nodes = self.document.nodes[&node_id].childs.clone();
+
+        for (child, child_node) in childs.into_iter().zip(nodes) {
+            self.nodes.insert(child, child_node);
+            self.map_childs(child, child_node);
+        }
+    }
+}
+
+/// Empty container of the same kind, or the value itself
+fn container (value: &Value) -> Value {
+    match value {
+        Value::Node(_) => Value::Node(Vec::new()),
+        Value::Array(_) => Value::Array(Vec::new()),
+        value => value.clone()
+    }
+}
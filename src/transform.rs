@@ -0,0 +1,160 @@
+use std::io::Error;
+
+use crate::{ Hson, Kind, Node, NodeRef, Value, escape, unescape, OPEN_CURLY, CLOSE_CURLY, OPEN_ARR, CLOSE_ARR, DOUBLE_QUOTES, COLONS, COMMA };
+
+
+/// What becomes of a node in a transformed document
+#[derive(PartialEq, Clone, Debug)]
+pub enum Transform {
+    /// Keep the node, its childs are transformed
+    Keep,
+    /// Leave the node and its childs out
+    Drop,
+    /// Keep the node key with another value, the value is not transformed
+    Replace(Value),
+    /// Keep the node under another unescaped key, its childs are transformed.
+    /// Array elements have no key and are kept as is
+    Rename(String),
+    /// Put the transformed node childs in place of the node, leaves are dropped. Array elements
+    /// unwrapped in an object take the node key, object entries unwrapped in an array lose theirs
+    Unwrap
+}
+
+
+impl Hson {
+    /// Build a new document in one pass, the function tells what becomes of every node but the root.
+    /// Parents are handled before their childs, dropped or replaced nodes childs are not visited
+    pub fn transform<F> (&self, mut f: F) -> Result<Hson, Error>
+        where F: FnMut(NodeRef) -> Transform {
+        let mut hson = Hson::new();
+
+        if let Some(root) = self.indexes.first() {
+            let entries = self.transform_childs(*root, &mut f);
+            hson.build(0, None, &Value::Node(entries));
+        }
+
+        Ok(hson)
+    }
+
+    /// Write a value at the end of the data along with its nodes, without parsing it.
+    /// A value without parent is the root. Returns the value node id
+    fn build (&mut self, parent: u64, key: Option<[usize; 2]>, value: &Value) -> u64 {
+        let root = parent == 0;
+        self.id_count += 1;
+        let id = self.id_count;
+        self.indexes.push(id);
+        self.instances += 1;
+        let instance = self.instances;
+        if let Some(key) = key {
+            let k: String = self.data[key[0]..key[1]].iter().collect();
+            self.cache.entry(k).or_default().push(id);
+        }
+
+        let start = self.data.len();
+        let mut childs = Vec::new();
+        match value {
+            Value::Node(entries) => {
+                self.data.push(OPEN_CURLY);
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.data.push(COMMA);
+                    }
+
+                    self.data.push(DOUBLE_QUOTES);
+                    let begin = self.data.len();
+                    self.data.extend(escape(key).chars());
+                    let end = self.data.len();
+                    self.data.push(DOUBLE_QUOTES);
+                    self.data.push(COLONS);
+
+                    childs.push(self.build(id, Some([begin, end]), value));
+                }
+                self.data.push(CLOSE_CURLY);
+            },
+            Value::Array(values) => {
+                self.data.push(OPEN_ARR);
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        self.data.push(COMMA);
+                    }
+
+                    childs.push(self.build(id, None, value));
+                }
+                self.data.push(CLOSE_ARR);
+            },
+            value => self.data.extend(value.to_hson().chars())
+        }
+        let end = self.data.len();
+
+        let kind = value.kind();
+        let value = match kind {
+            _ if root => [start, end - 1],
+            Kind::Node |
+            Kind::Array |
+            Kind::String => [start + 1, end - 1],
+            _ => [start, end]
+        };
+        let key = match key {
+            Some(key) => key,
+            None if root => [1, 0],
+            None => [0, 0]
+        };
+
+        self.nodes.insert(id, Node {
+            root,
+            kind,
+            parent,
+            childs,
+            key,
+            value,
+            id,
+            opened: false,
+            instance
+        });
+
+        id
+    }
+
+    /// Transformed entries of a node childs, keys are ignored for array childs
+    fn transform_childs<F> (&self, node_id: u64, f: &mut F) -> Vec<(String, Value)>
+        where F: FnMut(NodeRef) -> Transform {
+        let mut entries = Vec::new();
+        let parent_kind = self.nodes[&node_id].kind.clone();
+
+        for uid in &self.nodes[&node_id].childs {
+            let node = match NodeRef::new(self, *uid) {
+                Some(node) => node,
+                None => continue
+            };
+            let key = if parent_kind == Kind::Array { String::new() } else { unescape(&node.key()) };
+
+            match f(node) {
+                Transform::Drop => {},
+                Transform::Replace(value) => entries.push((key, value)),
+                Transform::Rename(renamed) => {
+                    let key = if parent_kind == Kind::Array { key } else { renamed };
+                    entries.push((key, self.transform_value(*uid, f)));
+                },
+                Transform::Keep => entries.push((key, self.transform_value(*uid, f))),
+                Transform::Unwrap => {
+                    for (child_key, value) in self.transform_childs(*uid, f) {
+                        let child_key = if node.kind == Kind::Array { key.clone() } else { child_key };
+                        entries.push((child_key, value));
+                    }
+                }
+            }
+        }
+
+        entries
+    }
+
+    /// Node value with transformed childs
+    fn transform_value<F> (&self, node_id: u64, f: &mut F) -> Value
+        where F: FnMut(NodeRef) -> Transform {
+        match self.nodes[&node_id].kind {
+            Kind::Node => Value::Node(self.transform_childs(node_id, f)),
+            Kind::Array => Value::Array(self.transform_childs(node_id, f).into_iter().map(|(_, value)| value).collect()),
+            _ => self.get_value(node_id).unwrap_or(Value::Null)
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+
+use crate::{ Kind, BACKSLASH, DOUBLE_QUOTES };
+
+
+/// Typed hson value
+#[derive(PartialEq, Clone, Debug)]
+pub enum Value {
+    /// Object made of ordered key/value pairs, the same key can appear multiple times
+    Node(Vec<(String, Value)>),
+    Array(Vec<Value>),
+    Integer(i64),
+    /// Hson has no representation for `NaN` and infinities, they are written `null`
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Null
+}
+
+impl Value {
+    /// Node kind the value is stored as
+    pub fn kind (&self) -> Kind {
+        match self {
+            Value::Node(_) => Kind::Node,
+            Value::Array(_) => Kind::Array,
+            Value::Integer(_) => Kind::Integer,
+            Value::Float(_) => Kind::Float,
+            Value::String(_) => Kind::String,
+            Value::Bool(_) => Kind::Bool,
+            Value::Null => Kind::Undefined
+        }
+    }
+
+    /// Does the value hold nested values
+    pub fn has_childs (&self) -> bool {
+        match self {
+            Value::Node(v) => !v.is_empty(),
+            Value::Array(v) => !v.is_empty(),
+            _ => false
+        }
+    }
+
+    /// Hson representation of the value
+    pub fn to_hson (&self) -> String {
+        let mut s = String::new();
+        self.write(&mut s);
+
+        s
+    }
+
+    fn write (&self, s: &mut String) {
+        match self {
+            Value::Node(entries) => {
+                s.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        s.push(',');
+                    }
+
+                    s.push(DOUBLE_QUOTES);
+                    s.push_str(&escape(key));
+                    s.push(DOUBLE_QUOTES);
+                    s.push(':');
+                    value.write(s);
+                }
+                s.push('}');
+            },
+            Value::Array(values) => {
+                s.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        s.push(',');
+                    }
+
+                    value.write(s);
+                }
+                s.push(']');
+            },
+            Value::Integer(v) => s.push_str(&v.to_string()),
+            Value::Float(v) => {
+                if v.is_finite() {
+                    let f = v.to_string();
+                    s.push_str(&f);
+
+                    // Keep the value a float once parsed back
+                    if !f.contains('.') && !f.contains('e') {
+                        s.push_str(".0");
+                    }
+                } else {
+                    // Like JSON, non finite floats become null
+                    s.push_str("null");
+                }
+            },
+            Value::String(v) => {
+                s.push(DOUBLE_QUOTES);
+                s.push_str(&escape(v));
+                s.push(DOUBLE_QUOTES);
+            },
+            Value::Bool(v) => s.push_str(if *v { "true" } else { "false" }),
+            Value::Null => s.push_str("null")
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hson())
+    }
+}
+
+impl From<&str> for Value {
+    fn from (v: &str) -> Self {
+        Value::String(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from (v: String) -> Self {
+        Value::String(v)
+    }
+}
+
+impl From<&String> for Value {
+    fn from (v: &String) -> Self {
+        Value::String(v.clone())
+    }
+}
+
+impl From<char> for Value {
+    fn from (v: char) -> Self {
+        Value::String(v.to_string())
+    }
+}
+
+impl From<bool> for Value {
+    fn from (v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<f32> for Value {
+    fn from (v: f32) -> Self {
+        Value::Float(f64::from(v))
+    }
+}
+
+impl From<f64> for Value {
+    fn from (v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+macro_rules! from_integer {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from (v: $t) -> Self {
+                    Value::Integer(v as i64)
+                }
+            }
+        )*
+    };
+}
+
+from_integer!(i8, i16, i32, i64, isize, u8, u16, u32);
+
+macro_rules! from_unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                /// Values above `i64::MAX` do not fit an integer and are kept as floats
+                fn from (v: $t) -> Self {
+                    if v <= i64::MAX as $t {
+                        Value::Integer(v as i64)
+                    } else {
+                        Value::Float(v as f64)
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_unsigned!(u64, usize);
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from (v: Option<T>) -> Self {
+        match v {
+            Some(v) => v.into(),
+            None => Value::Null
+        }
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from (v: Vec<T>) -> Self {
+        Value::Array(v.into_iter().map(|v| v.into()).collect())
+    }
+}
+
+
+/// Escape double quotes and backslashes of a string to be stored between double quotes
+pub fn escape (s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if c == DOUBLE_QUOTES || c == BACKSLASH {
+            escaped.push(BACKSLASH);
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Revert `escape`
+pub fn unescape (s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == BACKSLASH {
+            if let Some(&next) = chars.peek() {
+                if next == DOUBLE_QUOTES || next == BACKSLASH {
+                    unescaped.push(next);
+                    chars.next();
+                    continue;
+                }
+            }
+        }
+
+        unescaped.push(c);
+    }
+
+    unescaped
+}
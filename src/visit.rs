@@ -0,0 +1,56 @@
+use crate::{ Hson, NodeRef };
+
+
+/// What a walk does after entering a node
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum VisitControl {
+    Continue,
+    /// Do not visit the node childs, the node is left right away
+    SkipChildren,
+    /// End the walk, no node is entered nor left anymore
+    Stop
+}
+
+/// Callbacks of a walk over the nodes
+pub trait Visitor {
+    /// Called before the node childs are visited
+    fn enter (&mut self, node: NodeRef) -> VisitControl;
+
+    /// Called once the node childs are visited
+    fn leave (&mut self, _node: NodeRef) {}
+}
+
+
+impl Hson {
+    /// Walk the nodes depth first from the root, returns false when the walk was stopped
+    pub fn walk<V: Visitor + ?Sized> (&self, visitor: &mut V) -> bool {
+        match self.indexes.first() {
+            Some(root) => self.walk_from(*root, visitor),
+            None => true
+        }
+    }
+
+    /// Walk a node and its descendants depth first
+    pub fn walk_from<V: Visitor + ?Sized> (&self, node_id: u64, visitor: &mut V) -> bool {
+        let node = match NodeRef::new(self, node_id) {
+            Some(node) => node,
+            None => return true
+        };
+
+        match visitor.enter(node) {
+            VisitControl::Stop => return false,
+            VisitControl::SkipChildren => {},
+            VisitControl::Continue => {
+                for uid in &node.node().childs {
+                    if !self.walk_from(*uid, visitor) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        visitor.leave(node);
+
+        true
+    }
+}
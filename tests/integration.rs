@@ -1,11 +1,16 @@
+#![allow(clippy::needless_borrow)]
+
 use std::fs::File;
 use std::io::prelude::*;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex };
 
 #[macro_use]
 extern crate lazy_static;
 
+#[macro_use]
 extern crate hson;
-use hson::{ Hson, Query, Ops, Search, Cast };
+use hson::{ Hson, Query, Ops, Search, Cast, Value, Event, EventInfo, IntegrityIssue, MergeStrategy, Change, HsonPath, Segment, Patch, PatchOp, Conflict, Replica, ReplicaOp, OpId, Kind, NodeRef, Visitor, VisitControl, Transform, Insertion, Modification, ObserveOptions, MutationKind, MutationRecord, diff, merge3 };
 
 
 lazy_static! {
@@ -117,6 +122,22 @@ fn parse_array () {
     assert_eq!(hson.indexes.len(), 17);
 }
 
+#[test]
+fn parse_escapes () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"a\\":"x\\","b":"y\":z","c\"":{"d":"\\\""}}"#).unwrap();
+
+    let root = hson.get_value(hson.indexes[0]).unwrap();
+    let expected = Value::Node(vec![
+        (String::from("a\\"), Value::from("x\\")),
+        (String::from("b"), Value::from("y\":z")),
+        (String::from("c\""), Value::Node(vec![(String::from("d"), Value::from("\\\""))]))
+    ]);
+
+    assert_eq!(hson.indexes.len(), 5);
+    assert_eq!(root, expected);
+}
+
 #[test]
 fn has_nodes_number_simple () {
     let mut hson = Hson::new();
@@ -298,7 +319,9 @@ fn insertion_long () {
                         }
                     }"#;
 
-    assert_eq!(hson.insert(results[0], 1, child).unwrap(), ());
+    let inserted = hson.insert(results[0], 1, child).unwrap();
+    assert_eq!(inserted.roots.len(), 3);
+    assert_eq!(inserted.all.len(), 8);
     assert_eq!(hson.indexes.len(), 303);
     assert_eq!(hson.nodes.keys().len(), 303);
 
@@ -321,9 +344,73 @@ fn insertion_small () {
                         "name": "foo"
                     }"#;
 
-    assert_eq!(hson.insert(results[0], 0, child).unwrap(), ());
+    let inserted = hson.insert(results[0], 0, child).unwrap();
+    assert_eq!(inserted.roots.len(), 2);
+    assert_eq!(inserted.all.len(), 4);
     assert_eq!(hson.indexes.len(), 29);
     assert_eq!(hson.nodes.keys().len(), 29);
+
+    let mut slice = Hson::new();
+    slice.parse(child).unwrap();
+    assert_eq!(inserted.mapping.len(), 4);
+    for uid in slice.indexes.iter().skip(1) {
+        let id = inserted.mapping[uid];
+        assert_eq!(hson.get_value(id), slice.get_value(*uid));
+    }
+}
+
+#[test]
+fn insertion_positions () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"a":{},"b":[],"c":{"x":1,"y":"s","z":[true]},"d":[1,"t",{"e":null}]}"#).unwrap();
+
+    let a = hson.search("a").unwrap()[0];
+    let b = hson.search("b").unwrap()[0];
+    let c = hson.search("c").unwrap()[0];
+    let d = hson.search("d").unwrap()[0];
+    let root = hson.get_root();
+
+    hson.append_child(a, r#"{"k":[1,2]}"#).unwrap();
+    hson.prepend_child(a, r#"{"j":0}"#).unwrap();
+    hson.insert(c, 1, r#"{"w":{"v":2}}"#).unwrap();
+    hson.insert(c, 0, r#"{"u":false}"#).unwrap();
+    hson.append_child(c, r#"{"last":"end"}"#).unwrap();
+    hson.insert(root, 0, r#"{"first":1}"#).unwrap();
+    hson.append_child(root, r#"{"tail":{}}"#).unwrap();
+    hson.array_insert(b, 0, Value::from(3)).unwrap();
+    hson.array_insert(b, 0, Value::from("s")).unwrap();
+    hson.array_push(b, Value::from(vec![1, 2])).unwrap();
+    hson.array_insert(d, 1, Value::Node(vec![(String::from("f"), Value::from(1))])).unwrap();
+    hson.array_push(d, Value::from(2)).unwrap();
+
+    assert_eq!(hson.stringify(), r#"{"first":1,"a":{"j":0,"k":[1,2]},"b":["s",3,[1,2]],"c":{"u":false,"x":1,"w":{"v":2},"y":"s","z":[true],"last":"end"},"d":[1,{"f":1},"t",{"e":null},2],"tail":{}}"#);
+    assert_consistent(&hson);
+    assert_eq!(hson.search("w v").unwrap().len(), 1);
+    assert_eq!(hson.search("f").unwrap().len(), 1);
+}
+
+#[test]
+fn insertion_kind_mismatch () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"a":[1,2,3],"b":{"c":1}}"#).unwrap();
+
+    let a = hson.search("a").unwrap()[0];
+    let first = hson.get_all_childs(a).unwrap()[0];
+    let c = hson.search("c").unwrap()[0];
+
+    assert_eq!(hson.insert(a, 0, r#"{"x":1}"#).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert!(hson.append_child(a, r#"{"x":1}"#).is_err());
+    assert!(hson.prepend_child(a, r#"{"x":1}"#).is_err());
+    assert!(hson.insert_before(first, r#"{"x":1}"#).is_err());
+    assert!(hson.insert_after(first, r#"{"x":1}"#).is_err());
+    assert!(hson.replace(first, r#"{"x":1}"#).is_err());
+    assert!(hson.insert_after(c, r#"{"x":1}"#).is_ok());
+
+    let b = hson.search("b").unwrap()[0];
+    assert!(hson.array_insert(b, 0, Value::from(1)).is_err());
+
+    assert_eq!(hson.stringify(), r#"{"a":[1,2,3],"b":{"c":1,"x":1}}"#);
+    assert_consistent(&hson);
 }
 
 #[test]
@@ -341,6 +428,35 @@ fn deletion () {
     assert_eq!(hson.nodes.keys().len(), 17);
 }
 
+#[test]
+fn deletion_positions () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"a":{"j":0},"b":[1,"s",[2]],"c":{"x":1,"y":"s","z":{"w":[true]}},"d":2}"#).unwrap();
+
+    let ids = |hson: &mut Hson, q: &str| hson.search(q).unwrap()[0];
+    let j = ids(&mut hson, "j");
+    let y = ids(&mut hson, "y");
+    let x = ids(&mut hson, "x");
+    let z = ids(&mut hson, "z");
+    let d = ids(&mut hson, "d");
+    let b = ids(&mut hson, "b");
+
+    hson.remove(j).unwrap();
+    assert_consistent(&hson);
+    hson.remove(y).unwrap();
+    hson.remove(x).unwrap();
+    assert_consistent(&hson);
+    hson.array_remove(b, 1).unwrap();
+    hson.array_remove(b, 0).unwrap();
+    assert_consistent(&hson);
+    hson.remove(d).unwrap();
+    hson.remove(z).unwrap();
+
+    assert_eq!(hson.stringify(), r#"{"a":{},"b":[[2]],"c":{}}"#);
+    assert_eq!(hson.nodes.len(), 6);
+    assert_consistent(&hson);
+}
+
 #[test]
 fn replacement () {
     let mut hson = Hson::new();
@@ -394,4 +510,1614 @@ fn vertex_cast () {
     let trusted = hson.get_vertex(attributes[0]).unwrap();
 
     assert_eq!(trusted.value_as_bool(), Some(true));
-}
\ No newline at end of file
+}
+
+#[test]
+fn macro_literal () {
+    let hson = hson!{
+        "div": {
+            "attrs": { "class": ["a", "b"], "tabindex": -1, "hidden": false },
+            "p": { "text": "x" },
+            "p": { "text": "y", "ratio": 0.5, "data": null }
+        }
+    };
+
+    let mut expected = Hson::new();
+    expected.parse(r#"{
+        "div": {
+            "attrs": { "class": ["a", "b"], "tabindex": -1, "hidden": false },
+            "p": { "text": "x" },
+            "p": { "text": "y", "ratio": 0.5, "data": null }
+        }
+    }"#).unwrap();
+
+    assert_eq!(hson.stringify(), expected.stringify());
+    assert_eq!(hson.indexes.len(), expected.indexes.len());
+}
+
+#[test]
+fn macro_interpolation () {
+    let text = "say \"hi\" \\";
+    let count = 3;
+    let classes = vec!["a", "b"];
+    let mut hson = hson!{
+        "p": {
+            "text": (text),
+            "count": (count + 1),
+            "class": (classes),
+            "empty": {}
+        }
+    };
+
+    let results = hson.search("p text").unwrap();
+    let vertex = hson.get_vertex(results[0]).unwrap();
+    assert_eq!(hson::unescape(&vertex.value), text);
+
+    let results = hson.search("p count").unwrap();
+    assert_eq!(hson.get_vertex(results[0]).unwrap().value_as_i64(), Some(4));
+
+    let results = hson.search("p class").unwrap();
+    assert_eq!(hson.get_vertex(results[0]).unwrap().value_as_array().unwrap(), vec!["a", "b"]);
+
+    assert_eq!(Value::from(1.0).to_hson(), "1.0");
+
+    assert_eq!(Value::from(i64::MAX as u64), Value::Integer(i64::MAX));
+    assert_eq!(Value::from(u64::MAX), Value::Float(u64::MAX as f64));
+
+    let mut hson = hson!{ "n": (u64::MAX) };
+    let results = hson.search("n").unwrap();
+    assert_eq!(hson.get_value(results[0]), Some(Value::Float(u64::MAX as f64)));
+
+    // Non finite floats are written null
+    assert_eq!(Value::Float(f64::INFINITY).to_hson(), "null");
+    let mut hson = hson!{ "e": (f64::NAN) };
+    assert_eq!(hson.stringify(), r#"{"e":null}"#);
+    let results = hson.search("e").unwrap();
+    assert_eq!(hson.get_value(results[0]), Some(Value::Null));
+}
+
+fn assert_consistent (hson: &Hson) {
+    let mut fresh = Hson::new();
+    fresh.parse(&hson.stringify()).unwrap();
+
+    assert_eq!(hson.indexes.len(), fresh.indexes.len());
+    assert_eq!(hson.nodes.len(), fresh.nodes.len());
+
+    for (id, fresh_id) in hson.indexes.iter().zip(fresh.indexes.iter()) {
+        let node = &hson.nodes[id];
+        let fresh_node = &fresh.nodes[fresh_id];
+
+        assert_eq!(node.kind, fresh_node.kind);
+        assert_eq!(node.key, fresh_node.key);
+        assert_eq!(node.value, fresh_node.value);
+        assert_eq!(node.childs.len(), fresh_node.childs.len());
+    }
+
+    assert_eq!(hson.check_integrity(), Ok(()));
+}
+
+#[test]
+fn set_value_in_place () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let results = hson.search("div p attrs id").unwrap();
+    let id = results[0];
+    let count = hson.indexes.len();
+
+    hson.set_value(id, Value::from("a \"longer\" value")).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.get_value(id), Some(Value::from("a \"longer\" value")));
+    assert_eq!(hson.search("div p attrs id").unwrap(), vec![id]);
+
+    hson.set_value(id, Value::Integer(7)).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.get_vertex(id).unwrap().value_as_i64(), Some(7));
+    assert_eq!(hson.indexes.len(), count);
+}
+
+#[test]
+fn set_value_with_childs () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let results = hson.search("div attrs").unwrap();
+    let id = results[0];
+    let count = hson.indexes.len();
+    let childs = hson.get_all_childs(id).unwrap().len();
+    let others: Vec<u64> = hson.indexes.iter().filter(|uid| !hson.is_descendant(id, **uid)).cloned().collect();
+
+    hson.set_value(id, Value::Node(vec![("title".to_string(), Value::from("t")), ("tabindex".to_string(), Value::from(1))])).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.nodes[&id].childs.len(), 2);
+    assert_eq!(hson.indexes.len(), count - childs + 2);
+    assert!(others.iter().all(|uid| hson.nodes.contains_key(uid)));
+    assert_eq!(hson.search("attrs title").unwrap().len(), 1);
+
+    hson.set_value(id, Value::Array(vec![Value::from(1), Value::Node(vec![("a".to_string(), Value::Array(vec![]))])])).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.get_all_childs(id).unwrap().len(), 3);
+
+    hson.set_value(id, Value::Null).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.get_value(id), Some(Value::Null));
+    assert_eq!(hson.search("attrs title").unwrap().len(), 0);
+}
+
+#[test]
+fn rename_key () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let results = hson.search("div p").unwrap();
+    let id = results[0];
+
+    hson.rename_key(id, "section").unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.get_vertex(id).unwrap().key, "section");
+    assert_eq!(hson.search("div section").unwrap(), vec![id]);
+    assert_eq!(hson.search("div p").unwrap().len(), results.len() - 1);
+}
+
+#[test]
+fn move_node () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"ul":{"li":{"text":"a"},"li":{"text":"b"}},"ul":{"li":{"text":"c"}}}"#).unwrap();
+
+    let lists = hson.search("ul").unwrap();
+    let items = hson.search("li").unwrap();
+    let text = hson.search_in(items[0], "text").unwrap()[0];
+
+    hson.move_node(items[0], lists[1], 1).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.stringify(), r#"{"ul":{"li":{"text":"b"}},"ul":{"li":{"text":"c"},"li":{"text":"a"}}}"#);
+    assert_eq!(hson.nodes[&items[0]].parent, lists[1]);
+    assert_eq!(hson.nodes[&text].parent, items[0]);
+    assert_eq!(hson.search("li").unwrap(), vec![items[1], items[2], items[0]]);
+
+    hson.move_node(items[0], lists[1], 0).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.stringify(), r#"{"ul":{"li":{"text":"b"}},"ul":{"li":{"text":"a"},"li":{"text":"c"}}}"#);
+
+    hson.move_node(text, lists[0], 0).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.stringify(), r#"{"ul":{"text":"a","li":{"text":"b"}},"ul":{"li":{},"li":{"text":"c"}}}"#);
+
+    assert!(hson.move_node(lists[0], items[1], 0).is_err());
+    assert!(hson.move_node(items[1], lists[1], 5).is_err());
+}
+
+#[test]
+fn copy_node () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let count = hson.indexes.len();
+    let p = hson.search("div p").unwrap()[0];
+    let size = hson.get_all_childs(p).unwrap().len() + 1;
+    let root = hson.get_root();
+    let position = hson.nodes[&root].childs.len();
+
+    let copy = hson.copy_node(p, root, position).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.indexes.len(), count + size);
+    assert_eq!(hson.nodes[&copy].parent, root);
+    assert_eq!(hson.get_value(copy), hson.get_value(p));
+    assert_eq!(hson.search(">p").unwrap(), vec![copy]);
+}
+
+#[test]
+fn relative_insertion () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"ul":{"li":{"text":"b"}}}"#).unwrap();
+
+    let list = hson.search("ul").unwrap()[0];
+    let item = hson.search("li").unwrap()[0];
+
+    let appended = hson.append_child(list, r#"{"li":{"text":"d"}}"#).unwrap();
+    let prepended = hson.prepend_child(list, r#"{"li":{"text":"a"}}"#).unwrap();
+    let after = hson.insert_after(item, r#"{"li":{"text":"c1"},"li":{"text":"c2"}}"#).unwrap();
+    let before = hson.insert_before(item, r#"{"hr":{}}"#).unwrap();
+    assert_consistent(&hson);
+
+    assert_eq!(hson.stringify(), r#"{"ul":{"li":{"text":"a"},"hr":{},"li":{"text":"b"},"li":{"text":"c1"},"li":{"text":"c2"},"li":{"text":"d"}}}"#);
+    assert_eq!(hson.nodes[&list].childs, vec![prepended[0], before[0], item, after[0], after[1], appended[0]]);
+
+    let root = hson.get_root();
+    assert!(hson.insert_before(root, r#"{"p":{}}"#).is_err());
+    assert!(hson.insert(list, 7, r#"{"p":{}}"#).is_err());
+    assert!(hson.append_child(list, r#""p":{}"#).is_err());
+    assert!(hson.append_child(list, r#"{"p":{}"#).is_err());
+    assert_eq!(hson.nodes[&list].childs.len(), 6);
+}
+
+#[test]
+fn insertion_keeps_positions () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let results = hson.query("div p attrs").unwrap();
+    let l = hson.nodes[&results[0]].childs.len();
+
+    hson.insert(results[0], 0, r#"{"class": ["a"], "name": "foo"}"#).unwrap();
+    assert_consistent(&hson);
+    hson.insert(results[0], l + 2, r#"{"x": 1}"#).unwrap();
+    assert_consistent(&hson);
+}
+
+#[test]
+fn insertion_result () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let attrs = hson.query("div p attrs").unwrap()[0];
+    let inserted = hson.insert(attrs, 1, r#"{"data": {"id": "x"}, "title": "t"}"#).unwrap();
+    assert_consistent(&hson);
+
+    assert_eq!(inserted.roots, hson.nodes[&attrs].childs[1..3].to_vec());
+    assert_eq!(inserted.all.len(), 3);
+    assert_eq!(inserted.mapping.len(), 3);
+    assert_eq!(hson.get_vertex(inserted.all[1]).unwrap().key, "id");
+
+    // Follow-up edits can target the new nodes right away
+    hson.set_value(inserted.all[1], Value::from("y")).unwrap();
+    let replaced = hson.replace(inserted.roots[1], r#"{"alt": "a", "lang": "en"}"#).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(replaced.roots.len(), 2);
+    assert_eq!(hson.nodes[&attrs].childs[2], replaced.roots[0]);
+    assert_eq!(hson.search("attrs data id='y'").unwrap(), vec![inserted.all[1]]);
+}
+
+#[test]
+fn deletion_keeps_positions () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"a":{"b":{"c":1,"d":2},"e":[{"f":1},"g",3]},"h":4}"#).unwrap();
+
+    for key in &["c", "d", "f", "h"] {
+        let id = hson.query(key).unwrap()[0];
+        hson.remove(id).unwrap();
+        assert_consistent(&hson);
+    }
+
+    let array = hson.query("e").unwrap()[0];
+    let element = hson.nodes[&array].childs[0];
+    hson.remove(element).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.stringify(), r#"{"a":{"b":{},"e":["g",3]}}"#);
+}
+
+#[test]
+fn array_operations () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"div":{"attrs":{"class":["main","visible"]},"items":[]}}"#).unwrap();
+
+    let class = hson.search("attrs class").unwrap()[0];
+    let items = hson.search("div items").unwrap()[0];
+
+    let id = hson.array_push(class, Value::from("wide")).unwrap();
+    assert_eq!(hson.nodes[&class].childs[2], id);
+    hson.array_insert(class, 0, Value::from(1)).unwrap();
+    hson.array_set(class, 2, Value::from("hidden")).unwrap();
+    hson.array_remove(class, 1).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.get_value(class), Some(Value::from(vec![Value::from(1), Value::from("hidden"), Value::from("wide")])));
+    assert_eq!(hson.nodes[&class].childs[2], id);
+
+    let object = Value::Node(vec![("id".to_string(), Value::from(12)), ("name".to_string(), Value::from("a"))]);
+    let element = hson.array_push(items, object.clone()).unwrap();
+    hson.array_insert(items, 0, Value::from(vec![true, false])).unwrap();
+    hson.array_push(items, Value::Null).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.stringify(), r#"{"div":{"attrs":{"class":[1,"hidden","wide"]},"items":[[true,false],{"id":12,"name":"a"},null]}}"#);
+    assert_eq!(hson.get_value(element), Some(object));
+    assert_eq!(hson.search("items id").unwrap().len(), 1);
+
+    hson.array_set(items, 1, Value::from("x")).unwrap();
+    hson.array_remove(items, 0).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(hson.stringify(), r#"{"div":{"attrs":{"class":[1,"hidden","wide"]},"items":["x",null]}}"#);
+    assert_eq!(hson.search("items id").unwrap().len(), 0);
+
+    assert!(hson.array_remove(items, 2).is_err());
+    assert!(hson.array_insert(items, 3, Value::Null).is_err());
+    let root = hson.get_root();
+    assert!(hson.array_push(root, Value::Null).is_err());
+}
+
+static TRANSACTION_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+fn on_transaction_event (_info: &EventInfo) {
+    TRANSACTION_EVENTS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn transaction_commit () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    hson.subscribe(Box::new(on_transaction_event));
+
+    let p = hson.search("div p").unwrap()[0];
+    let attrs = hson.search("div p attrs").unwrap()[0];
+
+    let inserted = hson.transaction(|tx| {
+        let inserted = tx.insert(attrs, 0, r#"{"title": "t"}"#)?;
+        tx.set_value(inserted.roots[0], Value::from("u"))?;
+
+        assert_eq!(TRANSACTION_EVENTS.load(Ordering::SeqCst), 0);
+        Ok(inserted)
+    }).unwrap();
+
+    assert_eq!(TRANSACTION_EVENTS.load(Ordering::SeqCst), 2);
+    assert_eq!(hson.get_value(inserted.roots[0]), Some(Value::from("u")));
+    assert_eq!(hson.nodes[&p].childs[0], attrs);
+}
+
+#[test]
+fn transaction_rollback () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let data = hson.stringify();
+    let indexes = hson.indexes.clone();
+    let attrs = hson.search("div p attrs").unwrap()[0];
+    let p = hson.search("div p").unwrap()[0];
+
+    let result = hson.transaction(|tx| {
+        tx.insert(attrs, 0, r#"{"title": "t"}"#)?;
+        tx.remove(p)?;
+        tx.insert(attrs, 0, r#"{"title": "t"}"#)?;
+        Ok(())
+    });
+
+    assert!(result.is_err());
+    assert_eq!(hson.stringify(), data);
+    assert_eq!(hson.indexes, indexes);
+    assert_eq!(hson.search("div p attrs").unwrap(), vec![attrs]);
+    assert_eq!(hson.search("title").unwrap().len(), 0);
+
+    // Nested transactions roll back on their own
+    hson.transaction(|tx| {
+        tx.insert(attrs, 0, r#"{"title": "t"}"#)?;
+        let nested = tx.transaction(|tx| {
+            tx.remove(p)?;
+            tx.remove(p)
+        });
+        assert!(nested.is_err());
+        Ok(())
+    }).unwrap();
+
+    assert_consistent(&hson);
+    assert_eq!(hson.search("div p attrs title").unwrap().len(), 1);
+}
+
+#[test]
+fn failed_replace_is_atomic () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let data = hson.stringify();
+    let p = hson.search("div p").unwrap()[0];
+
+    assert!(hson.replace(p, r#"{"a": 1}, {"b"}"#).is_err());
+    assert_eq!(hson.stringify(), data);
+    assert!(hson.nodes.contains_key(&p));
+
+    // The insertion is refused once the node is already erased
+    hson.enable_history(10);
+    let erased = Arc::new(AtomicUsize::new(0));
+    let counter = erased.clone();
+    hson.before_insert(Box::new(move |hson, _| {
+        if !hson.nodes.contains_key(&p) {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+
+        Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Read only"))
+    }));
+    let events = Arc::new(AtomicUsize::new(0));
+    let counter = events.clone();
+    hson.subscribe(Box::new(move |_| { counter.fetch_add(1, Ordering::SeqCst); }));
+
+    let indexes = hson.indexes.clone();
+    assert!(hson.replace(p, r#"{"a": 1}"#).is_err());
+    assert_eq!(erased.load(Ordering::SeqCst), 1);
+    assert_eq!(events.load(Ordering::SeqCst), 0);
+    assert_eq!(hson.stringify(), data);
+    assert_eq!(hson.indexes, indexes);
+    assert!(!hson.can_undo());
+    assert_consistent(&hson);
+
+    let class = hson.search("div attrs class").unwrap()[0];
+    let element = hson.get_all_childs(class).unwrap()[0];
+    assert!(hson.replace(element, r#"{"x": 1}"#).is_err());
+    assert!(hson.nodes.contains_key(&element));
+    assert_eq!(erased.load(Ordering::SeqCst), 1);
+    assert_eq!(hson.stringify(), data);
+}
+
+#[test]
+fn undo_redo () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    hson.enable_history(100);
+
+    let original = hson.stringify();
+    let original_indexes = hson.indexes.clone();
+    let p = hson.search("div p").unwrap()[0];
+    let attrs = hson.search("div p attrs").unwrap()[0];
+    let id = hson.search("div p attrs id").unwrap()[0];
+    let class = hson.search("div attrs class").unwrap()[0];
+    let root = hson.get_root();
+
+    let mut states = vec![hson.stringify()];
+    hson.insert(attrs, 1, r#"{"title": "t", "data": {"a": 1}}"#).unwrap();
+    states.push(hson.stringify());
+    hson.set_value(id, Value::from("new")).unwrap();
+    states.push(hson.stringify());
+    hson.set_value(attrs, Value::Node(vec![("x".to_string(), Value::Null)])).unwrap();
+    states.push(hson.stringify());
+    hson.rename_key(p, "section").unwrap();
+    states.push(hson.stringify());
+    hson.move_node(p, root, 0).unwrap();
+    states.push(hson.stringify());
+    hson.array_push(class, Value::from("z")).unwrap();
+    states.push(hson.stringify());
+    hson.remove(p).unwrap();
+    states.push(hson.stringify());
+
+    let final_indexes = hson.indexes.clone();
+
+    for state in states.iter().rev().skip(1) {
+        assert!(hson.undo().unwrap());
+        assert_consistent(&hson);
+        assert_eq!(&hson.stringify(), state);
+    }
+
+    assert!(!hson.undo().unwrap());
+    assert_eq!(hson.stringify(), original);
+    assert_eq!(hson.indexes, original_indexes);
+    assert_eq!(hson.search("div p attrs id").unwrap(), vec![id]);
+
+    for state in states.iter().skip(1) {
+        assert!(hson.redo().unwrap());
+        assert_consistent(&hson);
+        assert_eq!(&hson.stringify(), state);
+    }
+
+    assert!(!hson.redo().unwrap());
+    assert_eq!(hson.indexes, final_indexes);
+}
+
+#[test]
+fn undo_groups_and_limit () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    hson.enable_history(2);
+
+    let original = hson.stringify();
+    let attrs = hson.search("div p attrs").unwrap()[0];
+
+    hson.transaction(|tx| {
+        tx.append_child(attrs, r#"{"a": 1}"#)?;
+        tx.append_child(attrs, r#"{"b": 2}"#)?;
+        tx.replace(attrs, r#"{"attrs": {}}"#)?;
+        Ok(())
+    }).unwrap();
+
+    // A failed transaction is not journaled
+    let _ = hson.transaction(|tx| {
+        let attrs = tx.search("div p attrs").unwrap()[0];
+        tx.remove(attrs)?;
+        tx.remove(attrs)
+    });
+
+    assert!(hson.undo().unwrap());
+    assert_eq!(hson.stringify(), original);
+    assert!(!hson.can_undo());
+    assert!(hson.can_redo());
+
+    let p = hson.search("div p").unwrap()[0];
+    hson.rename_key(p, "a").unwrap();
+    hson.rename_key(p, "b").unwrap();
+    hson.rename_key(p, "c").unwrap();
+    assert!(!hson.can_redo());
+
+    assert!(hson.undo().unwrap());
+    assert!(hson.undo().unwrap());
+    assert!(!hson.undo().unwrap());
+    assert_eq!(hson.get_vertex(p).unwrap().key, "a");
+
+    // Undo and redo are refused in a transaction, the stacks are left as they are
+    assert!(hson.redo().unwrap());
+    let state = hson.stringify();
+    let result = hson.transaction(|tx| {
+        tx.undo()?;
+        Ok(())
+    });
+    assert_eq!(result.unwrap_err().to_string(), "Cannot undo or redo during a transaction");
+    assert!(hson.transaction(|tx| tx.redo()).is_err());
+    assert_eq!(hson.stringify(), state);
+    assert!(hson.undo().unwrap());
+    assert_eq!(hson.get_vertex(p).unwrap().key, "a");
+    assert!(hson.redo().unwrap());
+    assert_eq!(hson.stringify(), state);
+}
+
+#[test]
+fn integrity () {
+    for data in [&*SHORT_DATA, &*LONG_DATA, &*NUM_DATA, &*HTML_DATA, &*NESTED_DATA, &*ARRAY_DATA, &*INTRICATE_DATA].iter() {
+        let mut hson = Hson::new();
+        hson.parse(data).unwrap();
+        assert_eq!(hson.check_integrity(), Ok(()));
+    }
+
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let id = hson.search("div p").unwrap()[0];
+    let mut node = hson.nodes[&id].clone();
+    node.value[1] += 1;
+    hson.nodes.insert(id, node.clone());
+
+    let issues = hson.check_integrity().unwrap_err();
+    assert_eq!(issues, vec![IntegrityIssue::ValueSpan { node: id, expected: [node.value[0], node.value[1] - 1], found: node.value }]);
+
+    let removed = hson.indexes.pop().unwrap();
+    let issues = hson.check_integrity().unwrap_err();
+    assert!(issues.contains(&IntegrityIssue::UnindexedNode(removed)));
+
+    // The parser accepts entries the document structure does not allow
+    let mut hson = Hson::new();
+    hson.parse(r#"{"a":["x":1,1],"b":1"c":2,"d":[1,,2],"e":{"f":1,}}"#).unwrap();
+    let a = hson.search("a").unwrap()[0];
+    let d = hson.search("d").unwrap()[0];
+    let c = hson.search("c").unwrap()[0];
+    let f = hson.search("f").unwrap()[0];
+    let issues = hson.check_integrity().unwrap_err();
+    let separators: Vec<u64> = issues.iter().filter_map(|issue| match issue {
+        IntegrityIssue::Separator { node } => Some(*node),
+        _ => None
+    }).collect();
+
+    assert_eq!(separators, vec![c, hson.get_all_childs(a).unwrap()[0], hson.get_all_childs(d).unwrap()[1], f]);
+
+    let mut hson = Hson::new();
+    hson.parse(r#"{"a":{1,"b":2}}"#).unwrap();
+    assert!(hson.check_integrity().is_err());
+}
+
+#[test]
+fn integrity_checks () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    hson.set_integrity_checks(true);
+
+    let root = hson.get_root();
+    let div = hson.search("div").unwrap()[0];
+    hson.append_child(root, r#"{"footer":{"text":"bye"}}"#).unwrap();
+    hson.insert_before(div, r#"{"header":["a", 1]}"#).unwrap();
+    let p = hson.search("div p").unwrap()[0];
+    hson.set_value(p, Value::from(vec![1, 2])).unwrap();
+    hson.array_push(p, Value::from("x")).unwrap();
+    hson.rename_key(p, "span").unwrap();
+    hson.move_node(p, root, 0).unwrap();
+    hson.copy_node(p, div, 0).unwrap();
+    hson.remove(div).unwrap();
+    assert_eq!(hson.check_integrity(), Ok(()));
+
+    // Corruption is reported by the next operation
+    let text = hson.search("footer text").unwrap()[0];
+    let mut node = hson.nodes[&p].clone();
+    node.key[0] += 1;
+    hson.nodes.insert(p, node);
+    assert!(hson.set_value(text, Value::from("ciao")).is_err());
+}
+
+#[test]
+fn clone_and_fork () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    hson.enable_history(10);
+
+    let mut fork = hson.fork();
+    assert_eq!(fork, hson);
+    assert!(!fork.can_undo());
+
+    let p = fork.search("div p").unwrap()[0];
+    fork.remove(p).unwrap();
+    assert_ne!(fork, hson);
+    assert!(!fork.can_undo());
+    assert!(hson.search("div p").unwrap().contains(&p));
+
+    let clone = hson.clone();
+    assert_eq!(clone.stringify(), hson.stringify());
+    assert_eq!(format!("{}", clone), hson.stringify());
+    assert!(format!("{:?}", clone).starts_with("Hson"));
+
+    // Same content parsed with other ids
+    let mut other = Hson::new_slice(100, 0);
+    other.parse(&SHORT_DATA).unwrap();
+    assert_eq!(other, hson);
+
+    let div = hson.search("div").unwrap()[0];
+    let other_div = other.search("div").unwrap()[0];
+    let other_p = other.search("div p").unwrap()[0];
+    assert!(hson.subtree_eq(div, &other, other_div));
+    assert!(!hson.subtree_eq(div, &other, other_p));
+}
+
+#[test]
+fn extract () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let p = hson.search("div p").unwrap()[0];
+    let mut extracted = hson.extract(p).unwrap();
+    assert_eq!(extracted.stringify(), format!("{{\"p\":{}}}", hson.stringify_node(p).unwrap()));
+    assert_eq!(extracted.check_integrity(), Ok(()));
+    assert_eq!(extracted.indexes, (1..=extracted.nodes.len() as u64).collect::<Vec<u64>>());
+    assert_eq!(extracted.search("p attrs id").unwrap().len(), 1);
+
+    let root = extracted.get_root();
+    let copy = extracted.nodes[&root].childs[0];
+    assert!(hson.subtree_eq(p, &extracted, copy));
+
+    // Array element
+    let class = hson.search("div attrs class").unwrap()[0];
+    let element = hson.nodes[&class].childs[1];
+    let extracted = hson.extract(element).unwrap();
+    assert_eq!(extracted.stringify(), r#"{"":123}"#);
+    assert_eq!(extracted.check_integrity(), Ok(()));
+
+    let extracted = hson.extract(class).unwrap();
+    assert_eq!(extracted.check_integrity(), Ok(()));
+
+    let root = hson.get_root();
+    let extracted = hson.extract(root).unwrap();
+    assert_eq!(extracted.check_integrity(), Ok(()));
+
+    assert!(hson.extract(9999).is_none());
+}
+
+#[test]
+fn graft () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    hson.enable_history(10);
+
+    let mut other = Hson::new();
+    other.parse(r#"{"header":{"title":"Hi","tags":["a",{"b":1}]},"footer":null}"#).unwrap();
+    let other_header = other.search("header").unwrap()[0];
+
+    let div = hson.search("div").unwrap()[0];
+    let before = hson.stringify();
+    let result = hson.graft(div, 1, other).unwrap();
+    assert_consistent(&hson);
+    assert_eq!(result.roots.len(), 2);
+    assert_eq!(result.all.len(), 7);
+    assert_eq!(hson.nodes[&div].childs[1], result.roots[0]);
+    assert_eq!(hson.search("header").unwrap(), vec![result.mapping[&other_header]]);
+
+    // Empty parent and first position
+    let span = hson.search("div p span").unwrap()[0];
+    let mut other = Hson::new();
+    other.parse(r#"{"x":1}"#).unwrap();
+    hson.graft(span, 0, other.clone()).unwrap();
+    assert_consistent(&hson);
+    let root = hson.get_root();
+    hson.graft(root, 0, other.clone()).unwrap();
+    assert_consistent(&hson);
+    hson.graft(span, 2, other).unwrap();
+    assert_consistent(&hson);
+
+    let class = hson.search("div attrs class").unwrap()[0];
+    let mut other = Hson::new();
+    other.parse(r#"{"x":1}"#).unwrap();
+    assert!(hson.graft(class, 0, other).is_err());
+
+    while hson.can_undo() {
+        hson.undo().unwrap();
+    }
+    assert_eq!(hson.stringify(), before);
+    assert_consistent(&hson);
+}
+
+#[test]
+fn merge () {
+    let theme = r#"{"page":{"color":"red","font":{"size":12,"family":"serif"},"p":1,"p":2},"lang":"en"}"#;
+    let page = r#"{"page":{"font":{"size":14},"p":3,"p":4,"p":5,"title":"Home"}}"#;
+
+    let mut hson = Hson::new();
+    hson.parse(theme).unwrap();
+    let mut other = Hson::new();
+    other.parse(page).unwrap();
+
+    let mut merged = hson.fork();
+    merged.merge(&other, MergeStrategy::AppendChildren).unwrap();
+    assert_consistent(&merged);
+    assert_eq!(merged.stringify(), format!("{},{}", &theme[..theme.len() - 1], &page[1..]));
+
+    let mut merged = hson.fork();
+    merged.merge(&other, MergeStrategy::DeepMerge).unwrap();
+    assert_consistent(&merged);
+    assert_eq!(merged.stringify(), r#"{"page":{"color":"red","font":{"size":14,"family":"serif"},"p":3,"p":4,"p":5,"title":"Home"},"lang":"en"}"#);
+
+    let mut merged = hson.fork();
+    merged.merge(&other, MergeStrategy::ReplaceOnConflict).unwrap();
+    assert_consistent(&merged);
+    assert_eq!(merged.stringify(), format!("{},{}", &page[..page.len() - 1], r#""lang":"en"}"#));
+}
+
+#[test]
+fn structural_diff () {
+    let mut a = Hson::new();
+    a.parse(r#"{"page":{"title":"Old","p":1,"p":2,"list":[1,2,3],"meta":{"x":1},"footer":true,"gone":null}}"#).unwrap();
+    let mut b = Hson::new();
+    b.parse(r#"{"page":{"heading":"Old","p":1,"p":3,"list":[1,4,3,5],"footer":true,"meta":{"x":1,"y":[]},"new":{"a":"b"}}}"#).unwrap();
+
+    let path = |segments: Vec<Segment>| HsonPath { segments };
+    let key = |k: &str| Segment::Key(k.to_string(), None);
+    let changes = diff(&a, &b).unwrap();
+
+    assert_eq!(changes, vec![
+        Change::Removed { path: path(vec![key("page"), key("gone")]), value: Value::Null },
+        Change::KeyRenamed { path: path(vec![key("page"), key("title")]), before: "title".to_string(), after: "heading".to_string() },
+        Change::Moved { from: path(vec![key("page"), key("meta")]), to: path(vec![key("page"), key("meta")]), position: 5 },
+        Change::Added { path: path(vec![key("page"), key("new")]), position: 6, value: Value::Node(vec![("a".to_string(), Value::from("b"))]) },
+        Change::ValueChanged { path: path(vec![key("page"), Segment::Key("p".to_string(), Some(1))]), before: Value::from(2), after: Value::from(3) },
+        Change::ValueChanged { path: path(vec![key("page"), key("list"), Segment::Index(1)]), before: Value::from(2), after: Value::from(4) },
+        Change::Added { path: path(vec![key("page"), key("list"), Segment::Index(3)]), position: 3, value: Value::from(5) },
+        Change::Added { path: path(vec![key("page"), key("meta"), key("y")]), position: 1, value: Value::Array(Vec::new()) }
+    ]);
+    assert_eq!(changes[4].to_string(), "~ page>p[1]: 2 -> 3");
+
+    assert!(diff(&a, &a.clone()).unwrap().is_empty());
+
+    // A document without data is an empty object
+    let page = a.get_value(a.indexes[1]).unwrap();
+    let changes = diff(&Hson::new(), &a).unwrap();
+    assert_eq!(changes, vec![Change::Added { path: path(vec![key("page")]), position: 0, value: page.clone() }]);
+    let mut from_empty = Hson::new();
+    from_empty.parse("{}").unwrap();
+    from_empty.apply_patch(&Patch::from_changes(&changes)).unwrap();
+    assert_eq!(from_empty, a);
+    assert_eq!(diff(&a, &Hson::new()).unwrap(), vec![Change::Removed { path: path(vec![key("page")]), value: page }]);
+    assert!(diff(&Hson::new(), &Hson::new()).unwrap().is_empty());
+
+    // Only the entries out of the longest ordered run move
+    let mut a = Hson::new();
+    a.parse(r#"{"a":1,"b":2,"c":3,"d":4}"#).unwrap();
+    let mut b = Hson::new();
+    b.parse(r#"{"d":4,"c":3,"a":1,"b":2}"#).unwrap();
+
+    let changes = diff(&a, &b).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert!(changes.iter().all(|c| matches!(c, Change::Moved { .. })));
+
+    // Added entries do not count in the ordered run
+    let mut b = Hson::new();
+    b.parse(r#"{"x":[],"d":4,"y":{},"c":3,"z":0,"a":1,"b":2}"#).unwrap();
+    let changes = diff(&a, &b).unwrap();
+    assert_eq!(changes.iter().filter(|c| matches!(c, Change::Moved { .. })).count(), 2);
+    assert_eq!(changes.iter().filter(|c| matches!(c, Change::Added { .. })).count(), 3);
+    a.apply_patch(&Patch::from_changes(&changes)).unwrap();
+    assert_eq!(a, b);
+}
+
+#[test]
+fn patch () {
+    let pairs = vec![
+        (r#"{"page":{"title":"Old","p":1,"p":2,"list":[1,2,3],"meta":{"x":1},"footer":true,"gone":null}}"#,
+         r#"{"page":{"heading":"Old","p":1,"p":3,"list":[1,4,3,5],"footer":true,"meta":{"x":1,"y":[]},"new":{"a":"b"}}}"#),
+        (r#"{"a":1,"b":2,"c":3,"d":4}"#, r#"{"d":4,"c":3,"a":1,"b":2}"#),
+        (r#"{"div":{"p":{"t":"a"},"p":{"t":"b"},"p":{"t":"c"}}}"#, r#"{"div":{"p":{"t":"c"},"p":{"t":"a/[x]"}},"div":[]}"#),
+        (r#"{"l":[[1,2],{"a":[3]},"x"]}"#, r#"{"l":["x",[2],{"a":[3,4]}],"k/ey":{"a\"b":1}}"#)
+    ];
+
+    for (a, b) in pairs {
+        let mut hson = Hson::new();
+        hson.parse(a).unwrap();
+        let mut target = Hson::new();
+        target.parse(b).unwrap();
+
+        let patch = Patch::from_changes(&diff(&hson, &target).unwrap());
+        let text = patch.to_hson();
+        assert_eq!(Patch::parse(&text).unwrap(), patch);
+
+        hson.apply_patch(&Patch::parse(&text).unwrap()).unwrap();
+        assert_eq!(hson, target);
+        assert_consistent(&hson);
+    }
+
+    let path = HsonPath::from_pointer("/html/body/div[1]/p[0]/class/[2]").unwrap();
+    assert_eq!(path.to_pointer(), "/html/body/div[1]/p[0]/class/[2]");
+    assert_eq!(path.segments[2], Segment::Key("div".to_string(), Some(1)));
+    assert_eq!(path.segments[5], Segment::Index(2));
+    assert_eq!(HsonPath::from_pointer(r"/a\/b\[c").unwrap().segments, vec![Segment::Key("a/b[c".to_string(), None)]);
+    assert!(HsonPath::from_pointer("html").is_err());
+
+    // A failing operation leaves the document untouched
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    let before = hson.stringify();
+    let patch = Patch { ops: vec![
+        PatchOp::Remove { path: HsonPath::from_pointer("/div/div/p[1]").unwrap() },
+        PatchOp::Remove { path: HsonPath::from_pointer("/div/nothing").unwrap() }
+    ] };
+    assert!(hson.apply_patch(&patch).is_err());
+    assert_eq!(hson.stringify(), before);
+}
+
+#[test]
+fn three_way_merge () {
+    let parse = |s: &str| {
+        let mut hson = Hson::new();
+        hson.parse(s).unwrap();
+        hson
+    };
+
+    let base = parse(r#"{"page":{"title":"Home","p":"a","p":"b","list":[1,2,3],"footer":{"year":2019}}}"#);
+    let ours = parse(r#"{"page":{"title":"Welcome","p":"a","p":"b","list":[1,2,4],"footer":{"year":2019},"nav":[]}}"#);
+    let theirs = parse(r#"{"page":{"title":"Home","intro":"hi","p":"a","p":"c","list":[0,2,3]}}"#);
+
+    let merged = merge3(&base, &ours, &theirs).unwrap();
+    assert_eq!(merged.stringify(), r#"{"page":{"title":"Welcome","intro":"hi","p":"a","p":"c","list":[0,2,4],"nav":[]}}"#);
+    assert_consistent(&merged);
+
+    let theirs = parse(r#"{"page":{"title":"Start","p":"a","list":[1,2,3,4],"footer":{"year":2020}}}"#);
+    let ours = parse(r#"{"page":{"title":"Welcome","p":"a","p":"b","list":[1,2],"footer":{"year":2019}}}"#);
+    let conflicts = merge3(&base, &ours, &theirs).unwrap_err();
+
+    let path = |segments: Vec<Segment>| HsonPath { segments };
+    let key = |k: &str| Segment::Key(k.to_string(), None);
+    assert_eq!(conflicts, vec![
+        Conflict { path: path(vec![key("page"), key("title")]), base: Some(Value::from("Home")), ours: Some(Value::from("Welcome")), theirs: Some(Value::from("Start")) },
+        Conflict { path: path(vec![key("page"), key("list")]), base: Some(Value::from(vec![1, 2, 3])), ours: Some(Value::from(vec![1, 2])), theirs: Some(Value::from(vec![1, 2, 3, 4])) }
+    ]);
+
+    // Modified on one side, removed on the other
+    let theirs = parse(r#"{"page":{"title":"Home","p":"a","p":"b","list":[1,2,3]}}"#);
+    let ours = parse(r#"{"page":{"title":"Home","p":"a","p":"b","list":[1,2,3],"footer":{"year":2020}}}"#);
+    let conflicts = merge3(&base, &ours, &theirs).unwrap_err();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].path.to_string(), "page>footer");
+    assert_eq!(conflicts[0].theirs, None);
+
+    // Entries under a duplicated key are aligned rather than matched by occurrence
+    let base = parse(r#"{"div":{"t":"a"},"div":{"t":"b"}}"#);
+    let ours = parse(r#"{"div":{"t":"new"},"div":{"t":"a"},"div":{"t":"b"}}"#);
+    let theirs = parse(r#"{"div":{"t":"a"},"div":{"t":"b","c":1}}"#);
+    let merged = merge3(&base, &ours, &theirs).unwrap();
+    assert_eq!(merged.stringify(), r#"{"div":{"t":"new"},"div":{"t":"a"},"div":{"t":"b","c":1}}"#);
+
+    let theirs = parse(r#"{"div":{"t":"a"},"div":{"t":"c"}}"#);
+    let ours = parse(r#"{"div":{"t":"x"},"div":{"t":"a"},"div":{"t":"d"}}"#);
+    let conflicts = merge3(&base, &ours, &theirs).unwrap_err();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].path.to_string(), "div[2]>t");
+    assert_eq!(conflicts[0].ours, Some(Value::from("d")));
+    assert_eq!(conflicts[0].theirs, Some(Value::from("c")));
+}
+
+#[test]
+fn replicas_concurrent_edits () {
+    let mut a = Replica::new(1);
+    let mut b = Replica::new(2);
+
+    let root = a.root();
+    let div = a.insert(root, 0, Some("div"), Value::Node(vec![("p".to_string(), Value::from("x"))])).unwrap();
+    for op in a.take_ops() {
+        b.apply(op).unwrap();
+    }
+    assert_eq!(a.document(), b.document());
+
+    // Concurrent insertions at the same place, value updates and removals
+    a.insert(div, 0, Some("h1"), Value::from("a")).unwrap();
+    b.insert(div, 0, Some("h2"), Value::from("b")).unwrap();
+    let p = a.element_of(a.document().nodes[&a.node_of(div).unwrap()].childs[1]).unwrap();
+    a.set_value(p, Value::from("from a")).unwrap();
+    b.set_value(p, Value::from("from b")).unwrap();
+    b.insert(div, 2, Some("p"), Value::from(vec![1, 2])).unwrap();
+    a.remove(div).unwrap();
+
+    let (ops_a, ops_b) = (a.take_ops(), b.take_ops());
+    for op in ops_a.into_iter().rev() {
+        b.apply(op).unwrap();
+    }
+    for op in ops_b {
+        a.apply(op).unwrap();
+    }
+
+    assert_eq!(a.document().stringify(), "{}");
+    assert_eq!(a.document(), b.document());
+
+    // Concurrent container values, the last writer wins along with its childs
+    let x = a.insert(root, 0, Some("x"), Value::Node(vec![("a".to_string(), Value::from(1))])).unwrap();
+    for op in a.take_ops() {
+        b.apply(op).unwrap();
+    }
+    a.set_value(x, Value::Node(vec![("b".to_string(), Value::from(2))])).unwrap();
+    b.set_value(x, Value::Node(vec![("c".to_string(), Value::from(3))])).unwrap();
+    let (ops_a, ops_b) = (a.take_ops(), b.take_ops());
+    for op in ops_a.into_iter().rev() {
+        b.apply(op).unwrap();
+    }
+    for op in ops_b {
+        a.apply(op).unwrap();
+    }
+    assert_eq!(a.document().stringify(), r#"{"x":{"c":3}}"#);
+    assert_eq!(a.document(), b.document());
+    assert_consistent(a.document());
+
+    // An operation the document rejects stays buffered and leaves the replica as it was
+    let data = b.document().stringify();
+    let op = ReplicaOp::Set { id: OpId { counter: 100, replica: 1 }, target: root, value: Value::from(1) };
+    assert!(b.apply(op.clone()).is_err());
+    assert_eq!(b.buffered(), 1);
+    assert_eq!(b.document().stringify(), data);
+    b.insert(x, 0, Some("d"), Value::from(4)).unwrap();
+    assert_eq!(b.document().stringify(), r#"{"x":{"d":4,"c":3}}"#);
+}
+
+#[test]
+fn replicas_converge () {
+    // Deterministic pseudo random generator
+    let mut seed: u64 = 42;
+    let mut random = move |n: usize| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((seed >> 33) as usize) % n.max(1)
+    };
+
+    let mut replicas: Vec<Replica> = (1..=3).map(Replica::new).collect();
+    let mut inboxes: Vec<Vec<ReplicaOp>> = vec![Vec::new(); 3];
+
+    for round in 0..60 {
+        for (r, replica) in replicas.iter_mut().enumerate() {
+            let document = replica.document();
+            let nodes: Vec<u64> = document.indexes.clone();
+            let containers: Vec<u64> = nodes.iter().filter(|id| document.nodes[id].kind == Kind::Node || document.nodes[id].kind == Kind::Array).cloned().collect();
+            let node_id = nodes[random(nodes.len())];
+            let element = replica.element_of(node_id).unwrap();
+
+            match random(4) {
+                0 | 1 => {
+                    let parent_id = containers[random(containers.len())];
+                    let parent = replica.element_of(parent_id).unwrap();
+                    let position = random(replica.document().nodes[&parent_id].childs.len() + 1);
+                    let key = if replica.document().nodes[&parent_id].kind == Kind::Node { Some(["a", "b", "c"][random(3)]) } else { None };
+                    let value = match random(3) {
+                        0 => Value::from(round as i64),
+                        1 => Value::Node(vec![("k".to_string(), Value::from(r as i64))]),
+                        _ => Value::Array(vec![Value::from("x")])
+                    };
+                    replica.insert(parent, position, key, value).unwrap();
+                },
+                2 if element != replica.root() => replica.remove(element).unwrap(),
+                3 if element != replica.root() => replica.set_value(element, Value::from(format!("{}-{}", r, round))).unwrap(),
+                _ => {}
+            }
+
+            for op in replica.take_ops() {
+                for (i, inbox) in inboxes.iter_mut().enumerate() {
+                    if i != r {
+                        inbox.push(op.clone());
+                    }
+                }
+            }
+        }
+
+        // Deliver a shuffled part of the operations, some twice
+        for (r, inbox) in inboxes.iter_mut().enumerate() {
+            let count = random(inbox.len() + 1);
+            for _ in 0..count {
+                let op = inbox.remove(random(inbox.len()));
+                if random(5) == 0 {
+                    replicas[r].apply(op.clone()).unwrap();
+                }
+                replicas[r].apply(op).unwrap();
+            }
+        }
+    }
+
+    for (r, inbox) in inboxes.iter_mut().enumerate() {
+        while !inbox.is_empty() {
+            let op = inbox.remove(random(inbox.len()));
+            replicas[r].apply(op).unwrap();
+        }
+    }
+
+    for replica in &replicas {
+        assert_eq!(replica.buffered(), 0);
+        assert_eq!(replica.document(), replicas[0].document());
+        assert_consistent(replica.document());
+    }
+    assert!(replicas[0].document().indexes.len() > 1);
+}
+
+#[test]
+fn node_paths () {
+    for data in [&*SHORT_DATA, &*HTML_DATA, &*NESTED_DATA, &*ARRAY_DATA].iter() {
+        let mut hson = Hson::new();
+        hson.parse(data).unwrap();
+        let mut reloaded = Hson::new_slice(1000, 0);
+        reloaded.parse(&hson.stringify()).unwrap();
+
+        for (i, id) in hson.indexes.iter().enumerate() {
+            let path = hson.path_of(*id).unwrap();
+            let parsed: HsonPath = path.to_string().parse().unwrap();
+
+            assert_eq!(parsed, path);
+            assert_eq!(hson.resolve(&path), Some(*id));
+            assert_eq!(reloaded.resolve(&parsed), Some(reloaded.indexes[i]));
+        }
+    }
+
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let p = hson.search("div div p").unwrap()[1];
+    assert_eq!(hson.path_of(p).unwrap().to_string(), "div>div[0]>p[1]");
+
+    let class = hson.search("div attrs class").unwrap()[0];
+    let element = hson.nodes[&class].childs[2];
+    assert_eq!(hson.path_of(element).unwrap().to_string(), "div>attrs>class>[2]");
+    assert_eq!(hson.resolve(&"div>attrs>class>[2]".parse().unwrap()), Some(element));
+
+    let path: HsonPath = r"a\>b>c[3]>[0]".parse().unwrap();
+    assert_eq!(path.segments, vec![Segment::Key("a>b".to_string(), None), Segment::Key("c".to_string(), Some(3)), Segment::Index(0)]);
+    assert_eq!(path.to_string(), r"a\>b>c[3]>[0]");
+    assert!("a[x]".parse::<HsonPath>().is_err());
+    assert_eq!(hson.resolve(&"div>nothing".parse().unwrap()), None);
+    assert_eq!(hson.path_of(9999), None);
+
+    let mut hson = Hson::new();
+    hson.parse(r#"{"":1,"":{"a":[2,{"":3}]},"q\"k":4}"#).unwrap();
+
+    let empty = hson.nodes[&hson.indexes[0]].childs[1];
+    assert_eq!(hson.path_of(empty).unwrap().to_string(), r#"""[1]"#);
+    assert_eq!(hson.path_of(empty).unwrap().to_pointer(), r#"/""[1]"#);
+
+    for id in hson.indexes.iter().skip(1) {
+        let path = hson.path_of(*id).unwrap();
+        let parsed: HsonPath = path.to_string().parse().unwrap();
+        let pointer = HsonPath::from_pointer(&path.to_pointer()).unwrap();
+
+        assert_eq!(parsed, path);
+        assert_eq!(pointer, path);
+        assert_eq!(hson.resolve(&parsed), Some(*id));
+        assert_eq!(hson.resolve(&pointer), Some(*id));
+    }
+
+    let path: HsonPath = r#"""[1]>a>[1]>"""#.parse().unwrap();
+    assert_eq!(path.segments, vec![Segment::Key(String::new(), Some(1)), Segment::Key("a".to_string(), None), Segment::Index(1), Segment::Key(String::new(), None)]);
+
+    // Stray separators are rejected
+    assert_eq!(HsonPath::from_pointer("/a/b").unwrap().segments.len(), 2);
+    assert!(HsonPath::from_pointer("/a/b/").is_err());
+    assert!(HsonPath::from_pointer("//a").is_err());
+    assert!("a>>b".parse::<HsonPath>().is_err());
+    assert!("a>".parse::<HsonPath>().is_err());
+}
+
+#[test]
+fn node_metadata () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let div = hson.search("div").unwrap()[0];
+    let p = hson.search("div p").unwrap()[0];
+    let span = hson.search("div p span").unwrap()[0];
+    let class = hson.search("div attrs class").unwrap()[0];
+
+    assert_eq!(hson.set_meta(p, "dirty".to_string()).unwrap(), None);
+    assert_eq!(hson.set_meta(p, "clean".to_string()).unwrap(), Some("dirty".to_string()));
+    hson.set_meta(span, 7usize).unwrap();
+    hson.set_meta(class, 3usize).unwrap();
+    assert!(hson.set_meta(9999, 1usize).is_err());
+
+    assert_eq!(hson.get_meta::<String>(p), Some(&"clean".to_string()));
+    assert_eq!(hson.get_meta::<usize>(p), None);
+    *hson.get_meta_mut::<usize>(span).unwrap() += 1;
+    assert_eq!(hson.meta::<usize>(), vec![(class, &3), (span, &8)]);
+
+    // Edits elsewhere keep the metadata
+    hson.insert(div, 0, r#"{"h1":"Title"}"#).unwrap();
+    hson.set_value(class, Value::from("x")).unwrap();
+    assert_eq!(hson.get_meta::<usize>(span), Some(&8));
+    assert_eq!(hson.get_meta::<usize>(class), Some(&3));
+
+    // Moved nodes keep their metadata, copies get their own
+    hson.move_node(p, div, 0).unwrap();
+    assert_eq!(hson.get_meta::<String>(p), Some(&"clean".to_string()));
+    let copy = hson.copy_node(p, div, 0).unwrap();
+    let copied_span = hson.nodes[&copy].childs.iter().find(|id| hson.get_meta::<usize>(**id).is_some()).cloned().unwrap();
+    assert_eq!(hson.get_meta::<String>(copy), Some(&"clean".to_string()));
+    *hson.get_meta_mut::<usize>(copied_span).unwrap() = 1;
+    assert_eq!(hson.get_meta::<usize>(span), Some(&8));
+
+    // Rolled back transactions restore the metadata
+    let result = hson.transaction(|tx| {
+        tx.remove(p)?;
+        tx.set_meta(div, 0usize)?;
+        tx.remove(p)?;
+        Ok(())
+    });
+    assert!(result.is_err());
+    assert_eq!(hson.get_meta::<usize>(span), Some(&8));
+    assert_eq!(hson.get_meta::<usize>(div), None);
+
+    // Removed nodes drop their metadata
+    hson.remove(p).unwrap();
+    assert_eq!(hson.get_meta::<String>(p), None);
+    assert_eq!(hson.get_meta::<usize>(span), None);
+    assert_eq!(hson.meta::<String>(), vec![(copy, &"clean".to_string())]);
+    assert_eq!(hson.remove_meta::<String>(copy), Some("clean".to_string()));
+    assert!(hson.meta::<String>().is_empty());
+
+    // Extracted and grafted documents carry the metadata
+    let extracted = hson.extract(copy).unwrap();
+    assert_eq!(extracted.meta::<usize>().len(), 1);
+    let result = hson.graft(div, 0, extracted).unwrap();
+    assert!(result.all.iter().any(|id| hson.get_meta::<usize>(*id) == Some(&1)));
+    assert_eq!(hson.meta::<usize>().len(), 3);
+    assert_consistent(&hson);
+
+    // Shared state goes through an Arc<Mutex<_>>, the document still moves across threads
+    let shared = Arc::new(Mutex::new(0usize));
+    hson.set_meta(div, shared.clone()).unwrap();
+    let handle = std::thread::spawn(move || {
+        *hson.get_meta::<Arc<Mutex<usize>>>(div).unwrap().lock().unwrap() += 1;
+        hson
+    });
+    let hson = handle.join().unwrap();
+    assert_eq!(*shared.lock().unwrap(), 1);
+    assert_eq!(hson.meta::<Arc<Mutex<usize>>>().len(), 1);
+}
+
+#[test]
+fn borrowing_iterators () {
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+
+    let ids: Vec<u64> = hson.iter().map(|node| node.id).collect();
+    assert_eq!(ids, hson.indexes);
+    assert_eq!(hson.iter_dfs().map(|node| node.id).collect::<Vec<u64>>(), hson.indexes);
+    assert_eq!((&hson).into_iter().count(), hson.indexes.len());
+
+    // Nested and concurrent iterations
+    for node in &hson {
+        assert_eq!(node.childs().count(), node.childs.len());
+        assert_eq!(hson.iter_from(node.id).count(), hson.get_all_childs(node.id).unwrap().len() + 1);
+    }
+    let mut a = hson.iter();
+    let mut b = hson.iter_bfs();
+    assert_eq!(a.next().unwrap().id, b.next().unwrap().id);
+
+    // Breadth first yields the nodes by increasing depth
+    let depths: std::collections::HashMap<u64, usize> = hson.iter_with_depth().map(|(depth, id)| (id, depth)).collect();
+    let bfs: Vec<usize> = hson.iter_bfs().map(|node| depths[&node.id]).collect();
+    assert_eq!(bfs.len(), hson.indexes.len());
+    assert!(bfs.windows(2).all(|w| w[0] <= w[1]));
+
+    let div = hson.search("div").unwrap()[0];
+    let p = hson.search("div p").unwrap()[0];
+    assert_eq!(depths[&div], 1);
+    assert_eq!(depths[&p], 3);
+    for node in hson.iter() {
+        assert_eq!(node.depth(), depths[&node.id]);
+    }
+
+    let p_ref = hson.iter_from(p).next().unwrap();
+    assert_eq!(p_ref.key(), "p");
+    assert_eq!(p_ref.parent().unwrap().parent().unwrap().id, div);
+    assert_eq!(p_ref.value(), hson.get_value(p));
+    assert_eq!(hson.iter_from(9999).count(), 0);
+    assert_eq!(Hson::new().iter_dfs().count(), 0);
+}
+
+/// Renders the visited nodes and stops at the first `component` key
+struct Renderer {
+    out: String,
+    skip: String
+}
+
+impl Visitor for Renderer {
+    fn enter (&mut self, node: NodeRef) -> VisitControl {
+        let key = node.key();
+
+        if key == "component" {
+            return VisitControl::Stop;
+        }
+
+        self.out.push_str(&format!("<{}>", key));
+
+        if key == self.skip { VisitControl::SkipChildren } else { VisitControl::Continue }
+    }
+
+    fn leave (&mut self, node: NodeRef) {
+        self.out.push_str(&format!("</{}>", node.key()));
+    }
+}
+
+#[test]
+fn visitor () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"div":{"attrs":{"id":1},"p":{"span":"a"},"p":"b"}}"#).unwrap();
+
+    let mut renderer = Renderer { out: String::new(), skip: "attrs".to_string() };
+    assert!(hson.walk(&mut renderer));
+    assert_eq!(renderer.out, "<><div><attrs></attrs><p><span></span></p><p></p></div></>");
+
+    let p = hson.search("div p").unwrap()[0];
+    let mut renderer = Renderer { out: String::new(), skip: String::new() };
+    assert!(hson.walk_from(p, &mut renderer));
+    assert_eq!(renderer.out, "<p><span></span></p>");
+
+    // Stopping leaves no node
+    let mut hson = Hson::new();
+    hson.parse(&SHORT_DATA).unwrap();
+    let mut renderer = Renderer { out: String::new(), skip: "div".to_string() };
+    assert!(hson.walk(&mut renderer));
+    assert_eq!(renderer.out, "<><div></div></>");
+    let mut renderer = Renderer { out: String::new(), skip: "p".to_string() };
+    assert!(!hson.walk(&mut renderer));
+    assert!(renderer.out.ends_with("<div><p></p><p></p></div><div>"));
+}
+
+#[test]
+fn transform () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"div":{"attrs":{"class":["a","b"],"onClick":"go()"},"b":"Hi","script":{"src":"x.js"},"section":{"p":"One","p":"Two"},"list":["x",{"y":1}]}}"#).unwrap();
+    let before = hson.stringify();
+
+    let mut visited = 0;
+    let transformed = hson.transform(|node| {
+        visited += 1;
+
+        match node.key().as_str() {
+            "onClick" | "script" => Transform::Drop,
+            "b" => Transform::Rename("strong".to_string()),
+            "section" | "class" => Transform::Unwrap,
+            "p" => Transform::Replace(Value::Node(vec![("text".to_string(), node.value().unwrap())])),
+            "" if node.kind == Kind::Node => Transform::Unwrap,
+            _ => Transform::Keep
+        }
+    }).unwrap();
+
+    assert_eq!(transformed.stringify(), r#"{"div":{"attrs":{"class":"a","class":"b"},"strong":"Hi","p":{"text":"One"},"p":{"text":"Two"},"list":["x",1]}}"#);
+    assert_consistent(&transformed);
+    assert_eq!(hson.stringify(), before);
+    // Dropped and replaced nodes childs are not visited
+    assert_eq!(visited, 15);
+
+    let kept = hson.transform(|_| Transform::Keep).unwrap();
+    assert_eq!(kept, hson);
+    let empty = hson.transform(|_| Transform::Drop).unwrap();
+    assert_eq!(empty.stringify(), "{}");
+    assert_eq!(Hson::new().transform(|_| Transform::Keep).unwrap().indexes.len(), 0);
+}
+
+#[test]
+fn event_listeners () {
+    let mut hson = Hson::new();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let audit = log.clone();
+
+    let first = hson.subscribe(Box::new(move |info| audit.lock().unwrap().push(info.clone())));
+    let counter = Arc::new(AtomicUsize::new(0));
+    let count = counter.clone();
+    let second = hson.subscribe(Box::new(move |_| { count.fetch_add(1, Ordering::SeqCst); }));
+    assert_ne!(first, second);
+
+    hson.parse(&SHORT_DATA).unwrap();
+    let div = hson.search("div").unwrap()[0];
+    let p = hson.search("div p").unwrap()[0];
+    hson.insert(div, 0, r#"{"h1":"Title"}"#).unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+    assert_eq!(log.lock().unwrap()[1].event, Event::Insert);
+    assert_eq!(log.lock().unwrap()[1].parent, div);
+
+    // Clones and forks have no listener, hook nor observer
+    let hook = hson.before_remove(Box::new(|_, _| Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Locked"))));
+    let observer = hson.observe(div, ObserveOptions { child_list: true, values: false, subtree: true }, Box::new(|_| panic!("Observed by a clone"))).unwrap();
+    let mut clone = hson.clone();
+    clone.remove(p).unwrap();
+    let mut fork = hson.fork();
+    fork.remove(p).unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+    assert!(hson.remove(p).is_err());
+    assert!(hson.unsubscribe(hook));
+    assert!(hson.unsubscribe(observer));
+    hson.remove(p).unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 3);
+    let p = hson.search("div p").unwrap()[0];
+
+    assert!(hson.unsubscribe(first));
+    assert!(!hson.unsubscribe(first));
+    hson.remove(p).unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 4);
+    assert_eq!(log.lock().unwrap().len(), 3);
+
+    // Documents and their listeners can move to another thread
+    let mut seen = Vec::new();
+    hson.subscribe(Box::new(move |info| seen.push(info.node)));
+    let handle = std::thread::spawn(move || {
+        let div = hson.search("div").unwrap()[0];
+        hson.remove(div).unwrap();
+        hson
+    });
+    let mut hson = handle.join().unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 5);
+    assert!(hson.search("div").unwrap().is_empty());
+}
+
+#[test]
+fn event_payloads () {
+    let mut hson = Hson::new();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let events = log.clone();
+    hson.subscribe(Box::new(move |info: &EventInfo| events.lock().unwrap().push(info.clone())));
+    let take = || std::mem::take(&mut *log.lock().unwrap());
+
+    hson.parse(r#"{"div":{"p":"a","ul":["x","y"]}}"#).unwrap();
+    let parsed = take();
+    assert_eq!(parsed.len(), 1);
+    assert_eq!(parsed[0].event, Event::Parse);
+    assert_eq!(parsed[0].inserted, hson.indexes);
+
+    let div = hson.search("div").unwrap()[0];
+    let p = hson.search("div p").unwrap()[0];
+    let ul = hson.search("div ul").unwrap()[0];
+
+    // One event per inserted top level node
+    let result = hson.insert(div, 1, r#"{"h1":"Title","span":{"b":1}}"#).unwrap();
+    let inserted = take();
+    assert_eq!(inserted.len(), 2);
+    assert_eq!((inserted[0].event.clone(), inserted[0].node, inserted[0].parent, inserted[0].position), (Event::Insert, result.roots[0], div, 1));
+    assert_eq!(inserted[0].after, Some(Value::from("Title")));
+    assert_eq!(inserted[1].position, 2);
+    assert_eq!(inserted[1].inserted, result.all[1..].to_vec());
+
+    let element = hson.array_push(ul, Value::from("z")).unwrap();
+    let pushed = take();
+    assert_eq!((pushed[0].node, pushed[0].parent, pushed[0].position), (element, ul, 2));
+
+    // Updates carry the former and new values, and the dropped and created descendants
+    let span = result.roots[1];
+    let b = hson.search("div span b").unwrap()[0];
+    hson.set_value(span, Value::Array(vec![Value::from(1), Value::from(2)])).unwrap();
+    let updated = take();
+    assert_eq!(updated[0].event, Event::Update);
+    assert_eq!(updated[0].before, Some(Value::Node(vec![("b".to_string(), Value::from(1))])));
+    assert_eq!(updated[0].after, hson.get_value(span));
+    assert_eq!(updated[0].removed, vec![b]);
+    assert_eq!(updated[0].inserted, hson.nodes[&span].childs);
+    assert_eq!(updated[0].renamed, None);
+
+    hson.rename_key(p, "text").unwrap();
+    let renamed = take();
+    assert_eq!(renamed[0].renamed, Some(("p".to_string(), "text".to_string())));
+    assert_eq!(renamed[0].before, renamed[0].after);
+
+    let root = hson.get_root();
+    hson.move_node(p, root, 0).unwrap();
+    let moved = take();
+    assert_eq!((moved[0].event.clone(), moved[0].from, moved[0].parent, moved[0].position), (Event::Move, Some((div, 0)), root, 0));
+
+    let replaced = hson.replace(ul, r#"{"ol":[1],"hr":null}"#).unwrap();
+    let events = take();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].event.clone(), events[0].node, events[0].parent, events[0].position), (Event::Replace, ul, div, 2));
+    assert_eq!(events[0].before, Some(Value::Array(vec![Value::from("x"), Value::from("y"), Value::from("z")])));
+    assert_eq!(events[0].inserted, replaced.all);
+    assert_eq!(events[0].removed.len(), 4);
+
+    let removed_ids = {
+        let mut ids = vec![span];
+        ids.extend(hson.nodes[&span].childs.iter());
+        ids
+    };
+    hson.remove(span).unwrap();
+    let removed = take();
+    assert_eq!((removed[0].event.clone(), removed[0].node, removed[0].parent, removed[0].position), (Event::Remove, span, div, 1));
+    assert_eq!(removed[0].removed, removed_ids);
+    assert!(removed[0].after.is_none());
+
+    // Undo describes the restored nodes
+    hson.enable_history(10);
+    hson.remove(p).unwrap();
+    take();
+    hson.undo().unwrap();
+    let restored = take();
+    assert_eq!((restored[0].event.clone(), restored[0].node, restored[0].parent, restored[0].position), (Event::Insert, p, root, 0));
+}
+
+#[test]
+fn veto_hooks () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"body":{"locked":{"p":"Terms"},"main":{"p":"Hi","ul":[1,2]}}}"#).unwrap();
+    hson.enable_history(10);
+
+    let locked = hson.search("body locked").unwrap()[0];
+    let main = hson.search("body main").unwrap()[0];
+    let p = hson.search("body main p").unwrap()[0];
+    let ul = hson.search("body main ul").unwrap()[0];
+    let terms = hson.search("body locked p").unwrap()[0];
+
+    let no_script = hson.before_insert(Box::new(|_: &Hson, insertion: &Insertion| {
+        if insertion.entries.iter().any(|(key, _)| key == "script") {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "No script"));
+        }
+
+        Ok(())
+    }));
+    hson.before_insert(Box::new(move |hson: &Hson, insertion: &Insertion| {
+        if insertion.parent == locked || hson.is_descendant(locked, insertion.parent) {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Locked"));
+        }
+
+        Ok(())
+    }));
+    hson.before_remove(Box::new(move |hson: &Hson, node_id: u64| {
+        if node_id == locked || hson.is_descendant(locked, node_id) {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Locked"));
+        }
+
+        Ok(())
+    }));
+    hson.before_update(Box::new(move |hson: &Hson, modification: &Modification| {
+        if hson.is_descendant(locked, modification.node) || modification.value == Value::from("script") {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Locked"));
+        }
+
+        Ok(())
+    }));
+
+    let data = hson.stringify();
+    let events = Arc::new(AtomicUsize::new(0));
+    let count = events.clone();
+    hson.subscribe(Box::new(move |_| { count.fetch_add(1, Ordering::SeqCst); }));
+
+    assert_eq!(hson.insert(main, 0, r#"{"script":"alert()"}"#).unwrap_err().to_string(), "No script");
+    assert!(hson.append_child(terms, r#"{"b":1}"#).is_err());
+    assert!(hson.remove(terms).is_err());
+    assert!(hson.replace(locked, r#"{"x":1}"#).is_err());
+    assert!(hson.set_value(terms, Value::from("Changed")).is_err());
+    assert!(hson.rename_key(terms, "q").is_err());
+    assert!(hson.set_value(p, Value::from("script")).is_err());
+    assert!(hson.move_node(terms, main, 0).is_err());
+    assert!(hson.move_node(p, locked, 0).is_err());
+    assert!(hson.copy_node(p, locked, 0).is_err());
+    let script = Value::Node(vec![("script".to_string(), Value::from("alert()"))]);
+    assert_eq!(hson.set_value(p, script.clone()).unwrap_err().to_string(), "No script");
+    assert_eq!(hson.array_set(ul, 0, script).unwrap_err().to_string(), "No script");
+    assert_eq!(hson.set_value(9999, Value::from(1)).unwrap_err().to_string(), "Invalid uid 9999");
+    assert_eq!(hson.rename_key(9999, "q").unwrap_err().to_string(), "Invalid uid 9999");
+    assert_eq!(hson.stringify(), data);
+    assert_eq!(events.load(Ordering::SeqCst), 0);
+    assert!(!hson.can_undo());
+
+    // A veto inside a transaction rolls it back
+    let result = hson.transaction(|tx| {
+        tx.remove(p)?;
+        tx.array_push(ul, Value::from(3))?;
+        tx.insert(terms, 0, r#"{"b":1}"#)?;
+        Ok(())
+    });
+    assert!(result.is_err());
+    assert_eq!(hson.stringify(), data);
+
+    // Allowed changes go through
+    hson.set_value(p, Value::from("Hello")).unwrap();
+    hson.copy_node(terms, main, 0).unwrap();
+    assert_eq!(events.load(Ordering::SeqCst), 2);
+
+    assert!(hson.unsubscribe(no_script));
+    hson.insert(main, 0, r#"{"script":"alert()"}"#).unwrap();
+    assert_consistent(&hson);
+
+    // Forks do not keep the hooks
+    let mut fork = hson.fork();
+    fork.remove(terms).unwrap();
+}
+
+#[test]
+fn mutation_observers () {
+    let mut hson = Hson::new();
+    hson.parse(r#"{"app":{"nav":{"a":"Home"},"list":{"item":{"label":"One"},"item":{"label":"Two"}}}}"#).unwrap();
+
+    let app = hson.search("app").unwrap()[0];
+    let nav = hson.search("app nav").unwrap()[0];
+    let list = hson.search("app list").unwrap()[0];
+    let item = hson.search("app list item").unwrap()[0];
+    let label = hson.search("app list item label").unwrap()[0];
+
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let observed = batches.clone();
+    let id = hson.observe(list, ObserveOptions { subtree: true, child_list: true, values: true }, Box::new(move |records: &[MutationRecord]| {
+        observed.lock().unwrap().push(records.to_vec());
+    })).unwrap();
+    let childs = Arc::new(Mutex::new(Vec::new()));
+    let observed = childs.clone();
+    hson.observe(list, ObserveOptions { child_list: true, ..ObserveOptions::default() }, Box::new(move |records: &[MutationRecord]| {
+        observed.lock().unwrap().push(records.to_vec());
+    })).unwrap();
+    let take = |log: &Arc<Mutex<Vec<Vec<MutationRecord>>>>| std::mem::take(&mut *log.lock().unwrap());
+
+    // Changes outside the list are not reported
+    let a = hson.search("app nav a").unwrap()[0];
+    hson.set_value(a, Value::from("Index")).unwrap();
+    hson.insert(app, 0, r#"{"h1":"Title"}"#).unwrap();
+    assert!(take(&batches).is_empty());
+
+    // Value changes in the subtree
+    hson.set_value(label, Value::from("First")).unwrap();
+    let records = take(&batches);
+    assert_eq!(records.len(), 1);
+    assert_eq!((records[0][0].kind, records[0][0].target), (MutationKind::Value, label));
+    assert_eq!(records[0][0].info.before, Some(Value::from("One")));
+    assert!(take(&childs).is_empty());
+
+    // An operation inserting several nodes is a single batch
+    hson.insert(list, 2, r#"{"item":{"label":"Three"},"item":{"label":"Four"}}"#).unwrap();
+    let records = take(&batches);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].len(), 2);
+    assert!(records[0].iter().all(|record| record.kind == MutationKind::ChildList && record.target == list));
+    assert_eq!(take(&childs).len(), 1);
+
+    // Deeper child lists only reach the subtree observer
+    hson.append_child(item, r#"{"icon":"*"}"#).unwrap();
+    assert_eq!(take(&batches)[0][0].target, item);
+    assert!(take(&childs).is_empty());
+
+    // Moves out of and into the list
+    hson.move_node(item, nav, 0).unwrap();
+    let records = take(&batches);
+    assert_eq!(records[0][0].target, list);
+    assert_eq!(records[0][0].info.from, Some((list, 0)));
+    hson.move_node(item, list, 0).unwrap();
+    assert_eq!(take(&batches)[0][0].target, list);
+    assert_eq!(take(&childs).len(), 2);
+
+    // Transactions deliver one batch once committed, nothing when rolled back
+    hson.transaction(|tx| {
+        tx.remove(item)?;
+        tx.rename_key(list, "items")?;
+        let label = tx.search("app items item label").unwrap()[0];
+        tx.set_value(label, Value::from("2"))?;
+        assert!(take(&batches).is_empty());
+        Ok(())
+    }).unwrap();
+    let records = take(&batches);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].iter().map(|record| record.kind).collect::<Vec<MutationKind>>(), vec![MutationKind::ChildList, MutationKind::Value, MutationKind::Value]);
+    assert_eq!(records[0][1].info.renamed, Some(("list".to_string(), "items".to_string())));
+
+    let result = hson.transaction(|tx| {
+        tx.insert(list, 0, r#"{"item":{}}"#)?;
+        tx.remove(9999)?;
+        Ok(())
+    });
+    assert!(result.is_err());
+    assert!(take(&batches).is_empty());
+
+    assert!(hson.unsubscribe(id));
+    hson.insert(list, 0, r#"{"item":{}}"#).unwrap();
+    assert!(take(&batches).is_empty());
+    assert_eq!(take(&childs).len(), 2);
+
+    // Unknown nodes cannot be observed, observers go away with their node
+    assert!(hson.observe(9999, ObserveOptions::default(), Box::new(|_| {})).is_err());
+    let observer = hson.observe(nav, ObserveOptions::default(), Box::new(|_| {})).unwrap();
+    let rolled_back = hson.transaction(|tx| {
+        tx.remove(nav)?;
+        tx.remove(9999)?;
+        Ok(())
+    });
+    assert!(rolled_back.is_err());
+    hson.remove(list).unwrap();
+    assert!(hson.unsubscribe(observer));
+    let observer = hson.observe(nav, ObserveOptions::default(), Box::new(|_| {})).unwrap();
+    hson.remove(nav).unwrap();
+    assert!(!hson.unsubscribe(observer));
+}